@@ -1,7 +1,8 @@
-use std::fmt;
+use core::fmt;
 
 /// Represents a button on an Insteon device.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Button {
     /// The SET button.
     Set,
@@ -9,25 +10,25 @@ pub enum Button {
     Two,
     /// A tertiary button.
     Three,
+    /// Any other button, by number (from four to sixteen, as far as the modem can report).
+    Other(u8),
 }
 
 impl fmt::Display for Button {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::Button::*;
-        write!(
-            f,
-            "{}",
-            match self {
-                Set => "SET button",
-                Two => "Button two",
-                Three => "Button three",
-            }
-        )
+        match self {
+            Set => write!(f, "SET button"),
+            Two => write!(f, "Button two"),
+            Three => write!(f, "Button three"),
+            Other(number) => write!(f, "Button {}", number),
+        }
     }
 }
 
 /// An event related to buttons on a device.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum ButtonEvent {
     /// The button was tapped.
     Tapped(Button),
@@ -37,6 +38,44 @@ pub enum ButtonEvent {
     Released(Button),
 }
 
+impl ButtonEvent {
+    /// Decodes the event code the modem reports its own button events with (`0x54`).
+    ///
+    /// The high nibble identifies the button (counting from zero) and the low nibble the event.
+    pub fn from_im_code(code: u8) -> Option<Self> {
+        let button = match code >> 4 {
+            0x0 => Button::Set,
+            0x1 => Button::Two,
+            0x2 => Button::Three,
+            index => Button::Other(index + 1),
+        };
+        match code & 0x0F {
+            0x2 => Some(ButtonEvent::Tapped(button)),
+            0x3 => Some(ButtonEvent::Held(button)),
+            0x4 => Some(ButtonEvent::Released(button)),
+            _ => None,
+        }
+    }
+    /// Encodes the event as the modem would report it (the inverse of `from_im_code`).
+    ///
+    /// Returns `None` for `Button::Other` numbers outside four to sixteen, which have no code.
+    pub fn to_im_code(self) -> Option<u8> {
+        let (button, event) = match self {
+            ButtonEvent::Tapped(button) => (button, 0x2),
+            ButtonEvent::Held(button) => (button, 0x3),
+            ButtonEvent::Released(button) => (button, 0x4),
+        };
+        let button = match button {
+            Button::Set => 0x0,
+            Button::Two => 0x1,
+            Button::Three => 0x2,
+            Button::Other(number @ 4..=16) => number - 1,
+            Button::Other(_) => return None,
+        };
+        Some((button << 4) | event)
+    }
+}
+
 impl fmt::Display for ButtonEvent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -46,3 +85,31 @@ impl fmt::Display for ButtonEvent {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn im_code_round_trip() {
+        let mut decoded = 0;
+        for code in 0..=u8::MAX {
+            if let Some(event) = ButtonEvent::from_im_code(code) {
+                assert_eq!(event.to_im_code(), Some(code));
+                decoded += 1;
+            }
+        }
+        assert_eq!(decoded, 48);
+        assert_eq!(
+            ButtonEvent::from_im_code(0x13),
+            Some(ButtonEvent::Held(Button::Two))
+        );
+        let event = ButtonEvent::from_im_code(0x42).unwrap();
+        assert_eq!(event, ButtonEvent::Tapped(Button::Other(5)));
+        assert_eq!(event.to_string(), "Button 5 tapped.");
+        for &number in &[0, 1, 2, 3, 17, u8::MAX] {
+            let event = ButtonEvent::Held(Button::Other(number));
+            assert_eq!(event.to_im_code(), None, "Button {}", number);
+        }
+    }
+}
@@ -1,19 +1,57 @@
 //! Commands to be executed by the recipient.
 
+use core::{convert::TryFrom, fmt, ops::RangeInclusive};
+
+use crate::message::Group;
+
 /// An ALL-Link group number.
 ///
 /// This structure and `message::Group` will be consolidated into a better structure at a later point.
+/// Until then, the two convert into each other with `From`.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct GroupNumber(pub u8);
 
+impl GroupNumber {
+    /// The group numbers available for scenes.
+    ///
+    /// Group 0 is reserved: devices use it for the links made when their SET buttons are pressed
+    /// without choosing a group, so it isn't a scene of its own.
+    pub fn valid_range() -> RangeInclusive<u8> {
+        1..=255
+    }
+    /// Returns an iterator over the group numbers available for scenes (see `valid_range`).
+    pub fn all_valid() -> impl Iterator<Item = GroupNumber> {
+        Self::valid_range().map(GroupNumber)
+    }
+    /// Whether the group number is available for scenes (see `valid_range`).
+    pub fn is_valid(self) -> bool {
+        Self::valid_range().contains(&self.0)
+    }
+}
+
+impl From<Group> for GroupNumber {
+    fn from(group: Group) -> Self {
+        GroupNumber(group)
+    }
+}
+
+impl From<GroupNumber> for Group {
+    fn from(group: GroupNumber) -> Self {
+        group.0
+    }
+}
+
 /// The "on level" associated with an on command.
 ///
 /// It's not clear from the documentation what this really means.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct OnLevel(pub u8);
 
 /// Encodes a desired movement direction for dimming.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum BrightDim {
     /// Move in the direction of increasing brightness.
     Bright,
@@ -22,7 +60,12 @@ pub enum BrightDim {
 }
 
 /// The payload of an on command; either a group number or an "on level."
+///
+/// Both are encoded the same way, so which one a received command carries depends on the kind of
+/// message it arrived in; `Command::try_from` assumes a group number, while
+/// `message::Received::command` takes the message type into account.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum OnPayload {
     GroupNumber(GroupNumber),
     OnLevel(OnLevel),
@@ -30,6 +73,7 @@ pub enum OnPayload {
 
 /// Encodes a command to be faithfully executed by the recipient.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Command {
     On(OnPayload),
     FastOn(Option<GroupNumber>),
@@ -40,57 +84,171 @@ pub enum Command {
     Start(BrightDim),
     Stop,
     IdRequest,
-    StatusRequest,
+    /// Asks the device to acknowledge, without doing anything else.
+    Ping,
+    /// Requests the version of the Insteon engine the device runs.
+    EngineVersionRequest,
+    /// Requests one of the device's statuses, identified by the given type (`0x00` for its on
+    /// level, which every device reports; others, such as `0x01` for the LED status of some
+    /// keypads, depend on the device).
+    StatusRequest(u8),
+    /// Puts the device into linking mode for the given group.
     BeginLinking(GroupNumber),
+    /// Puts the device into unlinking mode for the given group.
     BeginUnlinking(GroupNumber),
+    /// Takes the device out of linking (or unlinking) mode.
+    ///
+    /// This is the command Insteon's documentation calls "exit linking mode" (`0x08`).
     CancelLinking,
+    /// Sets one of the device's operating flags, identified by the given code.
+    ///
+    /// I2CS devices only accept this as an extended message; see `message::OperatingFlags`.
+    SetOperatingFlags(u8),
+    /// Gets or sets one of the device's settings (an extended command).
+    ///
+    /// Gets and sets share this command and are told apart by the accompanying data; see
+    /// `message::ExtendedMessage::extended_get` and `message::ExtendedMessage::extended_set`.
+    ExtendedGetSet,
+    /// Reads or writes the device's ALL-Link database (an extended command).
+    ///
+    /// See `message::ExtendedMessage` for building the accompanying data.
+    ReadWriteDatabase,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Command::*;
+        match self {
+            On(OnPayload::GroupNumber(group)) => write!(f, "On(group {})", group.0),
+            On(OnPayload::OnLevel(level)) => write!(f, "On(level {})", level.0),
+            FastOn(group) => write_grouped(f, "FastOn", *group),
+            Off(group) => write_grouped(f, "Off", *group),
+            FastOff(group) => write_grouped(f, "FastOff", *group),
+            Bright(group) => write_grouped(f, "Bright", *group),
+            Dim(group) => write_grouped(f, "Dim", *group),
+            Start(BrightDim::Bright) => write!(f, "Start(bright)"),
+            Start(BrightDim::Dim) => write!(f, "Start(dim)"),
+            BeginLinking(group) => write_grouped(f, "BeginLinking", Some(*group)),
+            BeginUnlinking(group) => write_grouped(f, "BeginUnlinking", Some(*group)),
+            command => write!(f, "{:?}", command),
+        }
+    }
+}
+
+/// Writes the name of a command, followed by its group (if any).
+fn write_grouped(f: &mut fmt::Formatter, name: &str, group: Option<GroupNumber>) -> fmt::Result {
+    match group {
+        Some(group) => write!(f, "{}(group {})", name, group.0),
+        None => write!(f, "{}", name),
+    }
 }
 
 fn group_or_none(byte: u8) -> Option<GroupNumber> {
     if byte == 0 {
         None
     } else {
-        Some(GroupNumber(byte))
+        Some(byte.into())
+    }
+}
+
+/// The error returned when a pair of bytes doesn't encode a known command.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct UnknownCommand(pub [u8; 2]);
+
+impl fmt::Display for UnknownCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown command {:02X} {:02X}", self.0[0], self.0[1])
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for UnknownCommand {}
+
 impl Command {
     /// Attempts to parse a pair of bytes as a command.
     pub fn try_from(bytes: [u8; 2]) -> Option<Self> {
+        <Self as TryFrom<[u8; 2]>>::try_from(bytes).ok()
+    }
+    /// An on command for a single device, turning it on to the given level (0–255).
+    pub fn on_level(level: u8) -> Self {
+        Command::On(OnPayload::OnLevel(OnLevel(level)))
+    }
+    /// An on command for the members of the given group.
+    pub fn on_group(group: Group) -> Self {
+        Command::On(OnPayload::GroupNumber(group.into()))
+    }
+    /// Whether the command must be given a group number.
+    ///
+    /// An `On` command carrying an on level (rather than a group) doesn't take a group at all.
+    pub fn requires_group(&self) -> bool {
+        use self::Command::*;
+        matches!(
+            self,
+            On(OnPayload::GroupNumber(_)) | BeginLinking(_) | BeginUnlinking(_)
+        )
+    }
+    /// Whether the command can be given a group number (whether or not it requires one).
+    pub fn accepts_group(&self) -> bool {
+        use self::Command::*;
+        self.requires_group()
+            || matches!(self, FastOn(_) | Off(_) | FastOff(_) | Bright(_) | Dim(_))
+    }
+    /// Whether the command must be sent as an extended message (with 14 bytes of user data).
+    pub fn is_extended(&self) -> bool {
+        matches!(self, Command::ExtendedGetSet | Command::ReadWriteDatabase)
+    }
+    /// The first command byte (identifying the kind of command).
+    pub fn opcode(&self) -> u8 {
+        <[u8; 2]>::from(*self)[0]
+    }
+    /// The second command byte (the command's argument, if any).
+    pub fn payload(&self) -> u8 {
+        <[u8; 2]>::from(*self)[1]
+    }
+}
+
+impl TryFrom<[u8; 2]> for Command {
+    type Error = UnknownCommand;
+    fn try_from(bytes: [u8; 2]) -> Result<Self, Self::Error> {
         use self::Command::*;
-        match bytes[0] {
-            0x11 => Some(On(OnPayload::GroupNumber(GroupNumber(bytes[1])))),
-            0x12 => Some(FastOn(group_or_none(bytes[1]))),
-            0x13 => Some(Off(group_or_none(bytes[1]))),
-            0x14 => Some(FastOff(group_or_none(bytes[1]))),
-            0x15 => Some(Bright(group_or_none(bytes[1]))),
-            0x16 => Some(Dim(group_or_none(bytes[1]))),
+        Ok(match bytes[0] {
+            0x11 => On(OnPayload::GroupNumber(GroupNumber(bytes[1]))),
+            0x12 => FastOn(group_or_none(bytes[1])),
+            0x13 => Off(group_or_none(bytes[1])),
+            0x14 => FastOff(group_or_none(bytes[1])),
+            0x15 => Bright(group_or_none(bytes[1])),
+            0x16 => Dim(group_or_none(bytes[1])),
             0x17 => {
                 let dir = match bytes[1] {
                     0x01 => BrightDim::Bright,
                     _ => BrightDim::Dim,
                 };
-                Some(Start(dir))
+                Start(dir)
             }
-            0x18 => Some(Stop),
-            0x10 => Some(IdRequest),
-            0x19 => Some(StatusRequest),
-            0x09 => Some(BeginLinking(GroupNumber(bytes[1]))),
-            0x0A => Some(BeginUnlinking(GroupNumber(bytes[1]))),
-            0x08 => Some(CancelLinking),
-            _ => None,
-        }
+            0x18 => Stop,
+            0x10 => IdRequest,
+            0x0F => Ping,
+            0x0D => EngineVersionRequest,
+            0x19 => StatusRequest(bytes[1]),
+            0x09 => BeginLinking(GroupNumber(bytes[1])),
+            0x0A => BeginUnlinking(GroupNumber(bytes[1])),
+            0x08 => CancelLinking,
+            0x20 => SetOperatingFlags(bytes[1]),
+            0x2E => ExtendedGetSet,
+            0x2F => ReadWriteDatabase,
+            _ => return Err(UnknownCommand(bytes)),
+        })
     }
 }
 
 fn group_or_zero(group: Option<GroupNumber>) -> u8 {
-    group.map(|g| g.0).unwrap_or(0)
+    group.map(Group::from).unwrap_or(0)
 }
 
-impl Into<[u8; 2]> for Command {
-    fn into(self) -> [u8; 2] {
+impl From<Command> for [u8; 2] {
+    fn from(command: Command) -> Self {
         use self::Command::*;
-        match self {
+        match command {
             On(payload) => {
                 let two = match payload {
                     OnPayload::OnLevel(level) => level.0,
@@ -112,10 +270,91 @@ impl Into<[u8; 2]> for Command {
             }
             Stop => [0x18, 0],
             IdRequest => [0x10, 0],
-            StatusRequest => [0x19, 0],
+            Ping => [0x0F, 0],
+            EngineVersionRequest => [0x0D, 0],
+            StatusRequest(kind) => [0x19, kind],
             BeginLinking(group) => [0x09, group.0],
             BeginUnlinking(group) => [0x0A, group.0],
             CancelLinking => [0x08, 0],
+            SetOperatingFlags(code) => [0x20, code],
+            ExtendedGetSet => [0x2E, 0],
+            ReadWriteDatabase => [0x2F, 0],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_groups() {
+        assert!(!GroupNumber(0).is_valid());
+        assert_eq!(GroupNumber::all_valid().count(), 255);
+        assert!(GroupNumber::all_valid().all(GroupNumber::is_valid));
+    }
+
+    #[test]
+    fn group_conversions() {
+        for group in 0..=u8::MAX {
+            assert_eq!(Group::from(GroupNumber::from(group)), group);
+            assert_eq!(group_or_zero(group_or_none(group)), group);
+        }
+        // Group 0 stands for "no group" in commands, but converts like any other.
+        assert_eq!(group_or_none(0), None);
+        assert_eq!(group_or_zero(None), 0);
+        assert_eq!(GroupNumber::from(0), GroupNumber(0));
+        assert_eq!(group_or_none(1), Some(GroupNumber::from(1)));
+    }
+
+    #[test]
+    fn on_constructors() {
+        assert_eq!(
+            Command::on_level(0xFF),
+            Command::On(OnPayload::OnLevel(OnLevel(0xFF)))
+        );
+        assert_eq!(
+            Command::on_group(3),
+            Command::On(OnPayload::GroupNumber(GroupNumber(3)))
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let group = Some(GroupNumber(1));
+        let commands = [
+            Command::On(OnPayload::GroupNumber(GroupNumber(1))),
+            Command::FastOn(None),
+            Command::FastOn(group),
+            Command::Off(None),
+            Command::Off(group),
+            Command::FastOff(None),
+            Command::FastOff(group),
+            Command::Bright(None),
+            Command::Bright(group),
+            Command::Dim(None),
+            Command::Dim(group),
+            Command::Start(BrightDim::Bright),
+            Command::Start(BrightDim::Dim),
+            Command::Stop,
+            Command::IdRequest,
+            Command::Ping,
+            Command::EngineVersionRequest,
+            Command::StatusRequest(0x00),
+            Command::StatusRequest(0x01),
+            Command::BeginLinking(GroupNumber(1)),
+            Command::BeginUnlinking(GroupNumber(1)),
+            Command::CancelLinking,
+            Command::SetOperatingFlags(0x0A),
+            Command::ExtendedGetSet,
+            Command::ReadWriteDatabase,
+        ];
+        for &command in commands.iter() {
+            let bytes: [u8; 2] = command.into();
+            assert_eq!(Command::try_from(bytes), Some(command));
         }
+        // On levels can't be told apart from group numbers without the surrounding message.
+        let level: [u8; 2] = Command::On(OnPayload::OnLevel(OnLevel(0xFF))).into();
+        assert_eq!(level, [0x11, 0xFF]);
     }
 }
@@ -1,14 +1,29 @@
 //! Insteon device properties.
 
-use std::{fmt, ops::Index};
+use core::{fmt, ops::Index, slice};
+
+use crate::message::{Group, Message, MessageType};
 
 /// A device address.
 ///
 /// The ordering of the address bytes is always high, middle, low.
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Address([u8; 3]);
 
 impl Address {
+    /// The all-ones address (`FF.FF.FF`), which stands for every device.
+    pub const BROADCAST: Address = Address([0xFF, 0xFF, 0xFF]);
+    /// The null address (`00.00.00`), which stands for no device (e.g. in empty records).
+    pub const NULL: Address = Address([0x00, 0x00, 0x00]);
+    /// Whether this is the broadcast address (`FF.FF.FF`).
+    pub fn is_broadcast(&self) -> bool {
+        *self == Address::BROADCAST
+    }
+    /// Whether this is the null address (`00.00.00`).
+    pub fn is_null(&self) -> bool {
+        *self == Address::NULL
+    }
     /// Returns the (left-padded) combination of the three address bytes.
     pub fn reduce(self) -> u32 {
         let bytes = self.0;
@@ -19,6 +34,15 @@ impl Address {
         );
         (high << 16) + (middle << 8) + low
     }
+    /// Returns an iterator over the address bytes (high, middle, low).
+    pub fn iter(&self) -> slice::Iter<'_, u8> {
+        self.0.iter()
+    }
+    /// Returns the address byte at the given index (0 for high, 1 for middle, 2 for low), or
+    /// `None` if the index is out of range.
+    pub fn get(&self, index: usize) -> Option<&u8> {
+        self.0.get(index)
+    }
 }
 
 impl From<[u8; 3]> for Address {
@@ -27,6 +51,17 @@ impl From<[u8; 3]> for Address {
     }
 }
 
+impl From<Address> for [u8; 3] {
+    fn from(address: Address) -> Self {
+        address.0
+    }
+}
+
+/// Indexes the address bytes (0 for high, 1 for middle, 2 for low).
+///
+/// # Panics
+///
+/// Panics if the index is 3 or more; see `Address::get` for a non-panicking alternative.
 impl Index<usize> for Address {
     type Output = u8;
     fn index(&self, index: usize) -> &Self::Output {
@@ -34,8 +69,234 @@ impl Index<usize> for Address {
     }
 }
 
+impl<'a> IntoIterator for &'a Address {
+    type Item = &'a u8;
+    type IntoIter = slice::Iter<'a, u8>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
 impl fmt::Display for Address {
-    fn fmt(&self, _f: &mut fmt::Formatter) -> fmt::Result {
-        unimplemented!()
+    /// Formats the address in the conventional dotted hexadecimal form (e.g. `1A.2B.3C`).
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02X}.{:02X}.{:02X}", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+/// A device together with one of its ALL-Link groups.
+///
+/// This is handy as a key for routing ALL-Link traffic.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct DeviceGroup {
+    /// The address of the device.
+    pub address: Address,
+    /// The group number.
+    pub group: Group,
+}
+
+impl DeviceGroup {
+    /// Returns the device and group the given message is about, if it's about one.
+    ///
+    /// For received messages, only ALL-Link broadcasts and cleanups carry a group.
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        let (address, group) = match msg {
+            Message::Received(msg) => match msg.flags.message_type() {
+                // Broadcasts carry the group in place of the low byte of the recipient address.
+                MessageType::AllLinkBroadcast => (msg.from, msg.to[2]),
+                MessageType::AllLinkCleanup => (msg.from, msg.cmd2),
+                _ => return None,
+            },
+            Message::LinkComplete(result) => (result.id(), result.group()),
+            Message::LinkCleanupFailed(group, address) => (*address, *group),
+            Message::LinkRecordResponse(_, group, address, _) => (*address, *group),
+            Message::DatabaseRecordFound(record) => (record.id, record.group),
+            _ => return None,
+        };
+        Some(Self { address, group })
+    }
+}
+
+impl fmt::Display for DeviceGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (group {})", self.address, self.group)
+    }
+}
+
+/// A device category (and subcategory), as reported by the device.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Category {
+    /// The broad category of the device (e.g. dimmable lighting control).
+    pub category: u8,
+    /// The specific kind of device within its category.
+    pub subcategory: u8,
+}
+
+impl Category {
+    /// Returns the name of the (broad) category, if it's a known one.
+    pub fn name(self) -> Option<&'static str> {
+        Some(match self.category {
+            0x00 => "Generalized Controller",
+            0x01 => "Dimmable Lighting Control",
+            0x02 => "Switched Lighting Control",
+            0x03 => "Network Bridge",
+            0x04 => "Irrigation Control",
+            0x05 => "Climate Control",
+            0x06 => "Pool and Spa Control",
+            0x07 => "Sensors and Actuators",
+            0x08 => "Home Entertainment",
+            0x09 => "Energy Management",
+            0x0A => "Built-In Appliance Control",
+            0x0B => "Plumbing",
+            0x0C => "Communication",
+            0x0D => "Computer Control",
+            0x0E => "Window Coverings",
+            0x0F => "Access Control",
+            0x10 => "Security, Health, Safety",
+            0x11 => "Surveillance",
+            0x12 => "Automotive",
+            0x13 => "Pet Care",
+            0x14 => "Toys",
+            0x15 => "Timekeeping",
+            0x16 => "Holiday",
+            _ => return None,
+        })
+    }
+}
+
+impl From<[u8; 2]> for Category {
+    fn from(bytes: [u8; 2]) -> Self {
+        Self {
+            category: bytes[0],
+            subcategory: bytes[1],
+        }
+    }
+}
+
+impl From<Category> for [u8; 2] {
+    fn from(category: Category) -> Self {
+        [category.category, category.subcategory]
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:02X}.{:02X})",
+            self.name().unwrap_or("Unknown"),
+            self.category,
+            self.subcategory
+        )
+    }
+}
+
+/// A device's firmware version.
+///
+/// Versions compare in the obvious order, so newer firmware is greater.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Firmware(pub u8);
+
+impl Firmware {
+    /// Interprets a firmware byte as reported by a device or the modem, where `0x00` and `0xFF`
+    /// mean that no version was reported.
+    pub fn from_raw(byte: u8) -> Option<Self> {
+        match byte {
+            0x00 | 0xFF => None,
+            version => Some(Firmware(version)),
+        }
+    }
+}
+
+impl fmt::Display for Firmware {
+    /// Formats the version in hexadecimal (e.g. `0x41`), as it's usually written.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:02X}", self.0)
+    }
+}
+
+/// What a device reports about itself when asked to identify itself.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct DeviceInfo {
+    /// The device's address.
+    pub address: Address,
+    /// The device's category and subcategory.
+    pub category: Category,
+    /// The device's firmware version, if it reported one (see `Firmware::from_raw`).
+    pub firmware: Option<Firmware>,
+    /// The version of the Insteon engine the device runs.
+    pub engine: EngineVersion,
+}
+
+/// The version of the Insteon engine a device runs.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum EngineVersion {
+    /// The original Insteon engine.
+    I1,
+    /// The second-generation engine.
+    I2,
+    /// The second-generation engine with checksum and security (I2CS).
+    I2cs,
+    /// An engine version this crate doesn't know about.
+    Unknown(u8),
+}
+
+impl EngineVersion {
+    /// Whether extended messages sent to the device must carry a checksum.
+    pub fn requires_checksum(self) -> bool {
+        self == EngineVersion::I2cs
+    }
+}
+
+impl From<u8> for EngineVersion {
+    fn from(byte: u8) -> Self {
+        match byte {
+            0x00 => EngineVersion::I1,
+            0x01 => EngineVersion::I2,
+            0x02 => EngineVersion::I2cs,
+            _ => EngineVersion::Unknown(byte),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn address_display() {
+        let address = Address::from([0x1A, 0x2B, 0x03]);
+        assert_eq!(address.to_string(), "1A.2B.03");
+    }
+    #[test]
+    fn firmware() {
+        assert_eq!(Firmware::from_raw(0x00), None);
+        assert_eq!(Firmware::from_raw(0xFF), None);
+        assert_eq!(Firmware::from_raw(0x41), Some(Firmware(0x41)));
+        assert!(Firmware(0x41) > Firmware(0x3A));
+        assert_eq!(Firmware(0x0A).to_string(), "0x0A");
+    }
+    #[test]
+    fn get() {
+        let address = Address::from([0x1A, 0x2B, 0x3C]);
+        assert_eq!(address.get(0), Some(&0x1A));
+        assert_eq!(address.get(2), Some(&0x3C));
+        assert_eq!(address.get(3), None);
+    }
+    #[test]
+    fn special_addresses() {
+        assert!(Address::from([0xFF, 0xFF, 0xFF]).is_broadcast());
+        assert!(Address::from([0x00, 0x00, 0x00]).is_null());
+        let address = Address::from([0xFF, 0xFF, 0x00]);
+        assert!(!address.is_broadcast() && !address.is_null());
+    }
+    #[test]
+    fn category_display() {
+        let known = Category::from([0x01, 0x20]);
+        assert_eq!(known.to_string(), "Dimmable Lighting Control (01.20)");
+        let unknown = Category::from([0x42, 0x00]);
+        assert_eq!(unknown.to_string(), "Unknown (42.00)");
     }
 }
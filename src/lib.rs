@@ -1,3 +1,39 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+// Logging goes through these so that it compiles away entirely without the `log` feature. (They
+// go unused without the `std` feature, since only the serial I/O logs anything.)
+#[cfg(feature = "log")]
+#[allow(unused_macros)]
+macro_rules! trace {
+    ($($arg:tt)*) => { log::trace!($($arg)*) };
+}
+#[cfg(not(feature = "log"))]
+#[allow(unused_macros)]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+#[cfg(feature = "log")]
+#[allow(unused_macros)]
+macro_rules! debug {
+    ($($arg:tt)*) => { log::debug!($($arg)*) };
+}
+#[cfg(not(feature = "log"))]
+#[allow(unused_macros)]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(feature = "std")]
 pub use serialport;
 
 mod button;
@@ -5,9 +41,23 @@ pub mod command;
 pub mod device;
 mod link;
 pub mod message;
+#[cfg(all(test, feature = "std"))]
+mod mock;
+#[cfg(feature = "std")]
+pub mod modem;
+#[cfg(feature = "std")]
 mod port;
+#[cfg(feature = "std")]
 pub mod serial;
+#[cfg(feature = "std")]
+pub mod transport;
 pub mod x10;
 
-pub use self::port::open as open_port;
+#[cfg(feature = "std")]
+pub use self::modem::Modem;
+#[cfg(feature = "std")]
+pub use self::port::{open as open_port, open_tcp, PortError};
+#[cfg(feature = "std")]
 pub use self::serial::{next_message, next_response};
+#[cfg(feature = "std")]
+pub use self::transport::ModemTransport;
@@ -1,8 +1,11 @@
-use crate::device::Address;
+use core::fmt;
+
+use crate::device::{Address, Category, Firmware};
 use crate::message::Group;
 
 /// Stores link data from link messages.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct LinkData {
     pub data: [u8; 3],
 }
@@ -13,15 +16,145 @@ impl From<[u8; 3]> for LinkData {
     }
 }
 
-/// Encodes the result of a linking attempt.
+/// A record read directly from the modem's ALL-Link database memory.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct DatabaseRecord {
+    /// The memory address of the record.
+    pub address: [u8; 2],
+    /// The record control flags.
+    pub flags: u8,
+    /// The ALL-Link group of the record.
+    pub group: Group,
+    /// The ID of the linked device.
+    pub id: Address,
+    /// The link data.
+    pub data: LinkData,
+}
+
+/// A record in the modem's ALL-Link database, as reported while walking the database.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct LinkEntry {
+    /// The record control flags.
+    pub flags: u8,
+    /// The ALL-Link group of the record.
+    pub group: Group,
+    /// The ID of the linked device.
+    pub id: Address,
+    /// The link data.
+    pub data: LinkData,
+}
 
+/// An operation to perform on the modem's ALL-Link database.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum LinkRecordControl {
+    /// Find the first record matching the given group and address.
+    FindFirst,
+    /// Find the next record matching the given group and address.
+    FindNext,
+    /// Modify the first matching record, or add a new one if none is found.
+    Modify,
+    /// Add a record in which the modem is the controller.
+    ///
+    /// An existing controller record for the same group and address is modified instead.
+    AddController,
+    /// Add a record in which the modem is a responder.
+    ///
+    /// An existing responder record for the same group and address is modified instead.
+    AddResponder,
+    /// Delete the first matching record.
+    Delete,
+    /// A control code this crate doesn't know about.
+    Unknown(u8),
+}
+
+impl From<u8> for LinkRecordControl {
+    fn from(code: u8) -> Self {
+        use self::LinkRecordControl::*;
+        match code {
+            0x00 => FindFirst,
+            0x01 => FindNext,
+            0x20 => Modify,
+            0x40 => AddController,
+            0x41 => AddResponder,
+            0x80 => Delete,
+            code => Unknown(code),
+        }
+    }
+}
+
+impl From<LinkRecordControl> for u8 {
+    fn from(control: LinkRecordControl) -> Self {
+        use self::LinkRecordControl::*;
+        match control {
+            FindFirst => 0x00,
+            FindNext => 0x01,
+            Modify => 0x20,
+            AddController => 0x40,
+            AddResponder => 0x41,
+            Delete => 0x80,
+            Unknown(code) => code,
+        }
+    }
+}
+
+/// The modem's role in an ALL-Link.
+///
+/// The same codes are used to put the modem into linking mode (see `serial::start_linking`).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum LinkRole {
+    /// The modem controls the other device (`0x01`).
+    Controller,
+    /// The modem responds to the other device (`0x00`).
+    Responder,
+    /// The modem takes whichever role is appropriate, determined by the order in which the SET
+    /// buttons are pressed (`0x03`).
+    ///
+    /// This is only used when starting to link; completed links have a definite role.
+    Either,
+    /// The link was deleted (`0xFF`).
+    Deleted,
+    /// A role code this crate doesn't know about.
+    Unknown(u8),
+}
+
+impl From<u8> for LinkRole {
+    fn from(code: u8) -> Self {
+        use self::LinkRole::*;
+        match code {
+            0x00 => Responder,
+            0x01 => Controller,
+            0x03 => Either,
+            0xFF => Deleted,
+            code => Unknown(code),
+        }
+    }
+}
+
+impl From<LinkRole> for u8 {
+    fn from(role: LinkRole) -> Self {
+        use self::LinkRole::*;
+        match role {
+            Responder => 0x00,
+            Controller => 0x01,
+            Either => 0x03,
+            Deleted => 0xFF,
+            Unknown(code) => code,
+        }
+    }
+}
+
+/// Encodes the result of a linking attempt.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct LinkResult {
     is_controller: Option<bool>,
     group: Group,
     id: Address,
-    category: [u8; 2],
-    firmware: Option<u8>,
+    category: Category,
+    firmware: Option<Firmware>,
 }
 
 impl LinkResult {
@@ -29,6 +162,14 @@ impl LinkResult {
     pub fn group(&self) -> Group {
         self.group
     }
+    /// The modem's role in the link.
+    pub fn role(&self) -> LinkRole {
+        match self.is_controller {
+            Some(true) => LinkRole::Controller,
+            Some(false) => LinkRole::Responder,
+            None => LinkRole::Deleted,
+        }
+    }
     /// Whether the modem is a slave to this device.
     pub fn is_slave(&self) -> bool {
         self.is_controller == Some(false)
@@ -53,15 +194,15 @@ impl LinkResult {
     pub fn id(&self) -> Address {
         self.id
     }
-    /// The category of the slave (responder) device that was linked.
+    /// The category (and subcategory) of the slave (responder) device that was linked.
     ///
     /// ## Notes
     ///
-    /// This byte is just junk when the modem is not the master (controller), so `None` is
+    /// These bytes are just junk when the modem is not the master (controller), so `None` is
     /// returned in the case that the modem is the slave (responder).
-    pub fn category(&self) -> Option<u8> {
+    pub fn category(&self) -> Option<Category> {
         if self.is_controller() {
-            Some(self.category[0])
+            Some(self.category)
         } else {
             None
         }
@@ -74,7 +215,7 @@ impl LinkResult {
     /// returned in the case that the modem is the slave (responder).
     pub fn subcategory(&self) -> Option<u8> {
         if self.is_controller() {
-            Some(self.category[1])
+            Some(self.category.subcategory)
         } else {
             None
         }
@@ -88,7 +229,7 @@ impl LinkResult {
     /// 2. the device is old (newer devices do not report firmware versions).
     ///
     /// This makes this method pretty much useless.
-    pub fn firmware(&self) -> Option<u8> {
+    pub fn firmware(&self) -> Option<Firmware> {
         if self.is_controller() {
             self.firmware
         } else {
@@ -97,18 +238,58 @@ impl LinkResult {
     }
 }
 
+impl fmt::Display for LinkResult {
+    /// Describes the modem's role in the link, e.g. `controller of 1A.2B.3C in group 1`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.is_controller {
+            Some(true) => write!(f, "controller of {} in group {}", self.id, self.group),
+            Some(false) => write!(f, "responder to {} in group {}", self.id, self.group),
+            None => write!(f, "deleted link with {} in group {}", self.id, self.group),
+        }
+    }
+}
+
+/// The error returned when a link result's first byte isn't a known link code.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct InvalidLinkCode(pub u8);
+
+impl fmt::Display for InvalidLinkCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid link code {:#04x}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidLinkCode {}
+
+impl LinkResult {
+    /// Parses a link result, rejecting link codes other than `0x00` (responder), `0x01`
+    /// (controller), and `0xFF` (deleted).
+    ///
+    /// The `From` conversion treats every unknown code as a deletion instead, which can hide a
+    /// corrupted frame.
+    pub fn try_from(bytes: [u8; 8]) -> Result<Self, InvalidLinkCode> {
+        match bytes[0] {
+            0x00 | 0x01 | 0xFF => Ok(bytes.into()),
+            code => Err(InvalidLinkCode(code)),
+        }
+    }
+}
+
+/// Parses a link result leniently: the link code is `0x00` for a responder link, `0x01` for a
+/// controller link, and `0xFF` for a deleted link, and any other code is also taken to mean a
+/// deleted link (see `LinkResult::try_from` for a strict alternative).
 impl From<[u8; 8]> for LinkResult {
     fn from(bytes: [u8; 8]) -> Self {
         let is_controller = match bytes[0] {
             0x00 => Some(false),
             0x01 => Some(true),
-            0xFF | _ => None,
+            _ => None,
         };
         let group = bytes[1];
         let address = [bytes[2], bytes[3], bytes[4]].into();
-        let category = [bytes[5], bytes[6]];
-        let vers = bytes[7];
-        let firmware = if vers == 0xFF { None } else { Some(vers) };
+        let category = [bytes[5], bytes[6]].into();
+        let firmware = Firmware::from_raw(bytes[7]);
         Self {
             is_controller,
             group,
@@ -118,3 +299,85 @@ impl From<[u8; 8]> for LinkResult {
         }
     }
 }
+
+/// Encodes a link result as it appears on the wire (the inverse of the `From<[u8; 8]>`
+/// conversion). A missing firmware version is encoded as `0x00`.
+impl From<LinkResult> for [u8; 8] {
+    fn from(result: LinkResult) -> Self {
+        let code = match result.is_controller {
+            Some(false) => 0x00,
+            Some(true) => 0x01,
+            None => 0xFF,
+        };
+        let [high, middle, low]: [u8; 3] = result.id.into();
+        let [category, subcategory]: [u8; 2] = result.category.into();
+        let firmware = result.firmware.map_or(0x00, |firmware| firmware.0);
+        [
+            code,
+            result.group,
+            high,
+            middle,
+            low,
+            category,
+            subcategory,
+            firmware,
+        ]
+    }
+}
+
+/// The result of a linking attempt as plain data, for storing or comparing.
+///
+/// Unlike `LinkResult`, whose accessors hide the fields that are junk for the modem's role, this
+/// has them already filtered (so the optional fields are only ever set for controller links).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct LinkRecord {
+    /// The modem's role in the link.
+    pub role: LinkRole,
+    /// The group number assigned to the link.
+    pub group: Group,
+    /// The ID of the device that was linked.
+    pub id: Address,
+    /// The category (and subcategory) of the device that was linked.
+    pub category: Option<Category>,
+    /// The firmware version of the device that was linked (only reported by older devices).
+    pub firmware: Option<Firmware>,
+}
+
+impl From<LinkResult> for LinkRecord {
+    fn from(result: LinkResult) -> Self {
+        Self {
+            role: result.role(),
+            group: result.group(),
+            id: result.id(),
+            category: result.category(),
+            firmware: result.firmware(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_round_trip() {
+        for code in 0..=0xFF {
+            assert_eq!(u8::from(LinkRole::from(code)), code);
+            assert_eq!(u8::from(LinkRecordControl::from(code)), code);
+        }
+        assert_eq!(LinkRole::from(0x03), LinkRole::Either);
+        assert_eq!(
+            LinkRecordControl::from(0x41),
+            LinkRecordControl::AddResponder
+        );
+    }
+
+    #[test]
+    fn strict_link_result() {
+        let mut bytes = [0x01, 0x01, 0x11, 0x22, 0x33, 0x01, 0x20, 0x41];
+        assert!(LinkResult::try_from(bytes).unwrap().is_controller());
+        bytes[0] = 0x42;
+        assert_eq!(LinkResult::try_from(bytes), Err(InvalidLinkCode(0x42)));
+        assert_eq!(LinkResult::from(bytes).role(), LinkRole::Deleted);
+    }
+}
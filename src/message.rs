@@ -1,24 +1,583 @@
 //! Constructs for communication with the modem.
 
-use std::fmt;
+use core::fmt;
 
 pub use crate::button::*;
-use crate::command::Command;
-use crate::device::Address;
+use crate::command::{Command, GroupNumber, OnLevel, OnPayload};
+use crate::device::{Address, Category, Firmware};
 pub use crate::link::*;
 use crate::x10::Message as X10Message;
+use alloc::vec;
+use alloc::vec::Vec;
 
 // TODO: Make this a real type.
 /// Represents an ALL-Link device group.
 pub type Group = u8;
 
+/// The opcode that follows the start byte in every frame to and from the modem.
+///
+/// Opcodes `0x50` through `0x59` are messages sent by the modem on its own; the rest are commands
+/// sent to the modem, which are echoed back in the responses to them.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Opcode {
+    /// A standard message was received (`0x50`).
+    StandardMessageReceived,
+    /// An extended message was received (`0x51`).
+    ExtendedMessageReceived,
+    /// An X10 message was received (`0x52`).
+    X10Received,
+    /// An ALL-Link event completed (`0x53`).
+    LinkComplete,
+    /// A button on the modem was pressed, held, or released (`0x54`).
+    ButtonEvent,
+    /// The user reset the modem (`0x55`).
+    UserReset,
+    /// An ALL-Link cleanup failed (`0x56`).
+    LinkCleanupFailed,
+    /// An ALL-Link record response (`0x57`).
+    LinkRecordResponse,
+    /// The status of an ALL-Link cleanup (`0x58`).
+    LinkCleanupStatus,
+    /// A record was read from the ALL-Link database memory (`0x59`).
+    DatabaseRecordFound,
+    /// Get the modem's address, category, and firmware version (`0x60`).
+    GetInfo,
+    /// Send an ALL-Link command (`0x61`).
+    SendLinkCommand,
+    /// Send a standard or extended message (`0x62`).
+    SendMessage,
+    /// Send an X10 message (`0x63`).
+    SendX10,
+    /// Start ALL-Linking (`0x64`).
+    StartLinking,
+    /// Cancel ALL-Linking (`0x65`).
+    CancelLinking,
+    /// Set the modem's device category (`0x66`).
+    SetCategory,
+    /// Reset the modem to its factory defaults (`0x67`).
+    Reset,
+    /// Set the byte sent in ACKs to direct messages (`0x68`).
+    SetAckByte,
+    /// Get the first ALL-Link record (`0x69`).
+    GetFirstLinkRecord,
+    /// Get the next ALL-Link record (`0x6A`).
+    GetNextLinkRecord,
+    /// Set the modem's configuration (`0x6B`).
+    SetConfig,
+    /// Get the ALL-Link record for the sender of the last message (`0x6C`).
+    GetSenderLinkRecord,
+    /// Turn the modem's LED on (`0x6D`).
+    LedOn,
+    /// Turn the modem's LED off (`0x6E`).
+    LedOff,
+    /// Manage an ALL-Link record (`0x6F`).
+    ManageLinkRecord,
+    /// Set the byte sent in NAKs to direct messages (`0x70`).
+    SetNakByte,
+    /// Set the two bytes sent in ACKs to direct messages (`0x71`).
+    SetAckBytes,
+    /// Put the modem's radio to sleep (`0x72`).
+    Sleep,
+    /// Get the modem's configuration (`0x73`).
+    GetConfig,
+    /// Cancel the ALL-Link cleanup in progress (`0x74`).
+    CancelCleanup,
+    /// Read a record from the ALL-Link database memory (`0x75`).
+    ReadDatabase,
+    /// Make the modem beep (`0x77`).
+    Beep,
+    /// Set the modem's status byte (`0x78`).
+    SetStatus,
+    /// Set the link data for the next ALL-Link (`0x79`, RF modems only).
+    SetLinkData,
+    /// Set the number of application retries for new ALL-Links (`0x7A`, RF modems only).
+    SetRetries,
+    /// Set the RF frequency offset (`0x7B`, RF modems only).
+    SetFrequencyOffset,
+    /// Set the acknowledgement for TempLinc commands (`0x7C`, RF modems only).
+    SetTempLincAck,
+    /// An opcode this crate doesn't know about.
+    Unknown(u8),
+}
+
+impl Opcode {
+    /// Whether this is an opcode this crate knows how to decode.
+    pub fn is_known(self) -> bool {
+        !matches!(self, Opcode::Unknown(_))
+    }
+}
+
+impl From<u8> for Opcode {
+    fn from(byte: u8) -> Self {
+        use self::Opcode::*;
+        match byte {
+            0x50 => StandardMessageReceived,
+            0x51 => ExtendedMessageReceived,
+            0x52 => X10Received,
+            0x53 => LinkComplete,
+            0x54 => ButtonEvent,
+            0x55 => UserReset,
+            0x56 => LinkCleanupFailed,
+            0x57 => LinkRecordResponse,
+            0x58 => LinkCleanupStatus,
+            0x59 => DatabaseRecordFound,
+            0x60 => GetInfo,
+            0x61 => SendLinkCommand,
+            0x62 => SendMessage,
+            0x63 => SendX10,
+            0x64 => StartLinking,
+            0x65 => CancelLinking,
+            0x66 => SetCategory,
+            0x67 => Reset,
+            0x68 => SetAckByte,
+            0x69 => GetFirstLinkRecord,
+            0x6A => GetNextLinkRecord,
+            0x6B => SetConfig,
+            0x6C => GetSenderLinkRecord,
+            0x6D => LedOn,
+            0x6E => LedOff,
+            0x6F => ManageLinkRecord,
+            0x70 => SetNakByte,
+            0x71 => SetAckBytes,
+            0x72 => Sleep,
+            0x73 => GetConfig,
+            0x74 => CancelCleanup,
+            0x75 => ReadDatabase,
+            0x77 => Beep,
+            0x78 => SetStatus,
+            0x79 => SetLinkData,
+            0x7A => SetRetries,
+            0x7B => SetFrequencyOffset,
+            0x7C => SetTempLincAck,
+            byte => Unknown(byte),
+        }
+    }
+}
+
+impl From<Opcode> for u8 {
+    fn from(opcode: Opcode) -> Self {
+        use self::Opcode::*;
+        match opcode {
+            StandardMessageReceived => 0x50,
+            ExtendedMessageReceived => 0x51,
+            X10Received => 0x52,
+            LinkComplete => 0x53,
+            ButtonEvent => 0x54,
+            UserReset => 0x55,
+            LinkCleanupFailed => 0x56,
+            LinkRecordResponse => 0x57,
+            LinkCleanupStatus => 0x58,
+            DatabaseRecordFound => 0x59,
+            GetInfo => 0x60,
+            SendLinkCommand => 0x61,
+            SendMessage => 0x62,
+            SendX10 => 0x63,
+            StartLinking => 0x64,
+            CancelLinking => 0x65,
+            SetCategory => 0x66,
+            Reset => 0x67,
+            SetAckByte => 0x68,
+            GetFirstLinkRecord => 0x69,
+            GetNextLinkRecord => 0x6A,
+            SetConfig => 0x6B,
+            GetSenderLinkRecord => 0x6C,
+            LedOn => 0x6D,
+            LedOff => 0x6E,
+            ManageLinkRecord => 0x6F,
+            SetNakByte => 0x70,
+            SetAckBytes => 0x71,
+            Sleep => 0x72,
+            GetConfig => 0x73,
+            CancelCleanup => 0x74,
+            ReadDatabase => 0x75,
+            Beep => 0x77,
+            SetStatus => 0x78,
+            SetLinkData => 0x79,
+            SetRetries => 0x7A,
+            SetFrequencyOffset => 0x7B,
+            SetTempLincAck => 0x7C,
+            Unknown(byte) => byte,
+        }
+    }
+}
+
+/// Framing of the commands sent to the modem.
+pub mod frame {
+    use super::Opcode;
+    use alloc::vec::Vec;
+
+    /// The byte that starts every frame.
+    pub const START: u8 = 0x02;
+
+    /// Builds the frame for the given opcode and payload (the start byte, followed by the opcode,
+    /// followed by the payload).
+    ///
+    /// This is exactly what the functions in `serial` send, so it can be used to build the frames
+    /// expected of them.
+    pub fn encode(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(payload.len() + 2);
+        frame.push(START);
+        frame.push(opcode.into());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// A stream of bytes shaped like traffic from the modem, for fuzzing the decoders.
+    ///
+    /// The stream mixes the frames of well-formed messages (see `Message::to_bytes`), frames cut
+    /// short, frames with arbitrary opcodes and payloads, and stray bytes, so that fuzzing reaches
+    /// past the start byte and opcode.
+    #[cfg(feature = "arbitrary")]
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct ArbitraryFrames(pub Vec<u8>);
+
+    #[cfg(feature = "arbitrary")]
+    impl<'a> arbitrary::Arbitrary<'a> for ArbitraryFrames {
+        fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+            let mut bytes = Vec::new();
+            while !u.is_empty() {
+                match u.int_in_range(0..=3u8)? {
+                    0 => bytes.push(u.arbitrary()?),
+                    1 => {
+                        let frame = super::Message::arbitrary(u)?.to_bytes();
+                        let length = u.int_in_range(0..=frame.len())?;
+                        bytes.extend_from_slice(&frame[..length]);
+                    }
+                    2 => bytes.extend(super::Message::arbitrary(u)?.to_bytes()),
+                    _ => {
+                        let opcode = u.int_in_range(0x50..=0x7F)?;
+                        let length = u.int_in_range(0..=24)?.min(u.len());
+                        bytes.extend(encode(Opcode::from(opcode), u.bytes(length)?));
+                    }
+                }
+            }
+            Ok(ArbitraryFrames(bytes))
+        }
+    }
+}
+
+/// The type of a standard or extended message, as given by the top three bits of its flags.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MessageType {
+    /// A message addressed to a single device.
+    Direct,
+    /// An acknowledgement of a direct message.
+    DirectAck,
+    /// A direct message sent to each member of an ALL-Link group after a group broadcast.
+    AllLinkCleanup,
+    /// An acknowledgement of an ALL-Link cleanup message.
+    AllLinkCleanupAck,
+    /// A message broadcast to all devices.
+    Broadcast,
+    /// A negative acknowledgement of a direct message.
+    DirectNak,
+    /// A message broadcast to the members of an ALL-Link group.
+    AllLinkBroadcast,
+    /// A negative acknowledgement of an ALL-Link cleanup message.
+    AllLinkCleanupNak,
+}
+
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::MessageType::*;
+        write!(
+            f,
+            "{}",
+            match self {
+                Direct => "direct",
+                DirectAck => "direct ACK",
+                AllLinkCleanup => "ALL-Link cleanup",
+                AllLinkCleanupAck => "ALL-Link cleanup ACK",
+                Broadcast => "broadcast",
+                DirectNak => "direct NAK",
+                AllLinkBroadcast => "ALL-Link broadcast",
+                AllLinkCleanupNak => "ALL-Link cleanup NAK",
+            }
+        )
+    }
+}
+
+/// The flags byte of a standard or extended message.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct MessageFlags(pub u8);
+
+impl MessageFlags {
+    /// The bit marking a message as extended (carrying 14 bytes of user data).
+    pub const EXTENDED: u8 = 0x10;
+    /// The type of the message.
+    pub fn message_type(self) -> MessageType {
+        use self::MessageType::*;
+        match self.0 >> 5 {
+            0b000 => Direct,
+            0b001 => DirectAck,
+            0b010 => AllLinkCleanup,
+            0b011 => AllLinkCleanupAck,
+            0b100 => Broadcast,
+            0b101 => DirectNak,
+            0b110 => AllLinkBroadcast,
+            _ => AllLinkCleanupNak,
+        }
+    }
+    /// Whether the message is extended (carrying 14 bytes of user data).
+    pub fn is_extended(self) -> bool {
+        self.0 & Self::EXTENDED != 0
+    }
+    /// The number of times the message may still be retransmitted.
+    pub fn hops_left(self) -> u8 {
+        (self.0 >> 2) & 0b11
+    }
+    /// The number of times the message could be retransmitted when it was first sent.
+    pub fn max_hops(self) -> u8 {
+        self.0 & 0b11
+    }
+}
+
+impl From<u8> for MessageFlags {
+    fn from(byte: u8) -> Self {
+        MessageFlags(byte)
+    }
+}
+
+impl From<MessageFlags> for u8 {
+    fn from(flags: MessageFlags) -> Self {
+        flags.0
+    }
+}
+
+impl fmt::Display for MessageFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message_type())?;
+        if self.is_extended() {
+            write!(f, ", extended")?;
+        }
+        write!(f, ", {}/{} hops left", self.hops_left(), self.max_hops())
+    }
+}
+
+/// A standard or extended message received from another device.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Received {
+    /// The address of the device that sent the message.
+    pub from: Address,
+    /// The address the message was sent to.
+    pub to: Address,
+    /// The message flags.
+    pub flags: MessageFlags,
+    /// The first command byte.
+    pub cmd1: u8,
+    /// The second command byte.
+    pub cmd2: u8,
+    /// The user data, if this is an extended message.
+    ///
+    /// This is stored inline rather than boxed, which would cost an allocation per extended
+    /// message without making `Message` any smaller.
+    pub data: Option<[u8; 14]>,
+}
+
+impl Received {
+    /// Attempts to interpret the command bytes as a command.
+    ///
+    /// The second byte of an on command is an on level in direct messages (and their
+    /// acknowledgements) and a group number otherwise; this is taken into account here, unlike in
+    /// `Command::try_from`.
+    pub fn command(&self) -> Option<Command> {
+        use self::MessageType::*;
+        match Command::try_from([self.cmd1, self.cmd2])? {
+            Command::On(OnPayload::GroupNumber(GroupNumber(level))) => {
+                match self.flags.message_type() {
+                    Direct | DirectAck | DirectNak => {
+                        Some(Command::On(OnPayload::OnLevel(OnLevel(level))))
+                    }
+                    _ => Some(Command::On(OnPayload::GroupNumber(GroupNumber(level)))),
+                }
+            }
+            command => Some(command),
+        }
+    }
+    /// Reinterprets the message as a SET button pressed broadcast (which a device sends after
+    /// acknowledging an ID request), returning the device's category, its firmware version, and
+    /// the second command byte (whose meaning varies between devices).
+    ///
+    /// These broadcasts carry the category, subcategory, and firmware version in place of the
+    /// recipient's address, so `to` isn't a real address for them. Returns `None` for other
+    /// messages.
+    pub fn as_device_broadcast(&self) -> Option<(Category, u8, u8)> {
+        // The responder and controller variants differ only in the first command byte.
+        let is_set_button = self.cmd1 == 0x01 || self.cmd1 == 0x02;
+        if self.flags.message_type() != MessageType::Broadcast || !is_set_button {
+            return None;
+        }
+        let to = self.to;
+        Some(([to[0], to[1]].into(), to[2], self.cmd2))
+    }
+    /// The user data, if this is an extended message.
+    pub fn extended_data(&self) -> Option<&[u8; 14]> {
+        self.data.as_ref()
+    }
+    /// The user data byte numbered `n`, counting from 1 (so `d(1)` is D1 and `d(14)` is D14) as
+    /// in the Insteon documentation.
+    ///
+    /// Returns `None` for standard messages and for numbers outside 1–14.
+    pub fn d(&self, n: usize) -> Option<u8> {
+        let index = n.checked_sub(1)?;
+        self.data?.get(index).cloned()
+    }
+    /// Whether the last user data byte is the checksum of the rest of the message.
+    ///
+    /// Returns `None` for standard messages. Only I2CS devices are obliged to fill in the
+    /// checksum, so a mismatch doesn't necessarily mean the message was corrupted.
+    pub fn has_valid_checksum(&self) -> Option<bool> {
+        let data = self.data?;
+        Some(checksum([self.cmd1, self.cmd2], &data) == data[13])
+    }
+    /// The first user data byte (D1), which usually identifies the kind of extended command.
+    pub fn command_sub(&self) -> Option<u8> {
+        self.d(1)
+    }
+}
+
+/// Computes the checksum of an extended message with the given command and user data.
+///
+/// The checksum covers the two command bytes and the first 13 bytes of user data; it belongs in
+/// the last data byte. Devices running the I2CS engine reject extended messages without it.
+pub fn checksum(command: [u8; 2], data: &[u8]) -> u8 {
+    let sum = command
+        .iter()
+        .chain(data.iter().take(13))
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    (!sum).wrapping_add(1)
+}
+
+/// The command and user data of an extended message, with the checksum filled in.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ExtendedMessage {
+    /// The command to send.
+    pub command: Command,
+    /// The user data, ending with the checksum.
+    pub data: [u8; 14],
+}
+
+impl ExtendedMessage {
+    /// Creates an extended message with the given command and user data, appending the checksum.
+    pub fn new(command: Command, data: [u8; 13]) -> Self {
+        let mut full = [0; 14];
+        full[..13].copy_from_slice(&data);
+        full[13] = checksum(command.into(), &data);
+        Self {
+            command,
+            data: full,
+        }
+    }
+    /// Requests the settings for the given group (or button) of the device.
+    ///
+    /// The device replies with an extended message carrying the settings.
+    pub fn extended_get(group: Group) -> Self {
+        let mut data = [0; 13];
+        data[0] = group;
+        Self::new(Command::ExtendedGetSet, data)
+    }
+    /// Changes one of the settings for the given group (or button) of the device.
+    ///
+    /// The setting is identified by a device-specific code (e.g. `0x07` sets the LED brightness
+    /// of many switches).
+    pub fn extended_set(group: Group, setting: u8, value: u8) -> Self {
+        let mut data = [0; 13];
+        data[0] = group;
+        data[1] = setting;
+        data[2] = value;
+        Self::new(Command::ExtendedGetSet, data)
+    }
+    /// Requests the record at the given address in the device's ALL-Link database.
+    ///
+    /// The device replies with an extended message carrying the record.
+    pub fn read_database_record(address: u16) -> Self {
+        let [high, low] = address.to_be_bytes();
+        let mut data = [0; 13];
+        data[2] = high;
+        data[3] = low;
+        data[4] = 0x01;
+        Self::new(Command::ReadWriteDatabase, data)
+    }
+    /// Writes a record at the given address in the device's ALL-Link database.
+    pub fn write_database_record(
+        address: u16,
+        flags: u8,
+        group: Group,
+        id: Address,
+        link: LinkData,
+    ) -> Self {
+        let [high, low] = address.to_be_bytes();
+        let mut data = [0; 13];
+        data[1] = 0x02;
+        data[2] = high;
+        data[3] = low;
+        data[4] = 0x08;
+        data[5..13].copy_from_slice(&[
+            flags,
+            group,
+            id[0],
+            id[1],
+            id[2],
+            link.data[0],
+            link.data[1],
+            link.data[2],
+        ]);
+        Self::new(Command::ReadWriteDatabase, data)
+    }
+}
+
+/// Behavioral flags stored by a device, as set with `Command::SetOperatingFlags`.
+///
+/// The codes used here are the ones common to SwitchLinc and KeypadLinc devices; other devices
+/// may not support every flag.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct OperatingFlags {
+    /// Whether linking with the SET button is disabled.
+    pub program_lock: bool,
+    /// Whether the LED blinks while the device transmits.
+    pub led_on_transmit: bool,
+    /// Whether the device turns back on at its previous level (rather than full).
+    pub resume_dim: bool,
+    /// Whether the LED is turned off entirely.
+    pub led_off: bool,
+    /// Whether the device beeps when its buttons are pressed.
+    pub key_beep: bool,
+}
+
+impl OperatingFlags {
+    /// Encodes the flags as extended messages to send to the device, one per flag.
+    pub fn messages(&self) -> [ExtendedMessage; 5] {
+        // Each flag has a pair of codes: the first sets it and the second clears it.
+        let code = |set: bool, on: u8| if set { on } else { on + 1 };
+        let message = |code| ExtendedMessage::new(Command::SetOperatingFlags(code), [0; 13]);
+        [
+            message(code(self.program_lock, 0x00)),
+            message(code(self.led_on_transmit, 0x02)),
+            message(code(self.resume_dim, 0x04)),
+            message(code(self.led_off, 0x08)),
+            message(code(self.key_beep, 0x0A)),
+        ]
+    }
+}
+
+impl From<ExtendedMessage> for (Command, [u8; 14]) {
+    fn from(msg: ExtendedMessage) -> Self {
+        (msg.command, msg.data)
+    }
+}
+
 /// Messages are notifications delivered by the modem to us.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Message {
     /// A message (either standard or extended) was received.
     ///
-    /// This message type is merely an acknowledgement.
-    Received(Address, Option<Command>, u8, Option<[u8; 14]>),
+    /// This may be an event (such as a switch's broadcast when it's pressed) or a device's
+    /// acknowledgement of something the host sent; see `is_event` and `is_acknowledgement`.
+    Received(Received),
     /// An X10 message was received.
     X10Received(X10Message),
     /// An ALL-Link event completed.
@@ -33,19 +592,228 @@ pub enum Message {
     LinkRecordResponse(u8, Group, Address, crate::link::LinkData),
     /// The All-Link cleanup completed (successfully or not).
     LinkCleanupStatus(bool),
-    DatabaseRecordFound([u8; 2], u8, u8, Address, crate::link::LinkData),
+    /// A record was read from the modem's ALL-Link database memory.
+    DatabaseRecordFound(DatabaseRecord),
+}
+
+/// A coarse classification of a received message, distinguishing replies to direct messages
+/// from scene (ALL-Link) traffic.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ReceivedKind {
+    /// A direct message addressed to the modem.
+    Direct,
+    /// A device acknowledged a direct message.
+    DirectAck,
+    /// A device refused a direct message.
+    DirectNak,
+    /// A broadcast to all devices (such as the one sent when a device's SET button is pressed).
+    Broadcast,
+    /// A broadcast to the members of an ALL-Link group.
+    AllLinkBroadcast,
+    /// An ALL-Link cleanup message, or an acknowledgement (positive or negative) of one.
+    AllLinkCleanup,
+}
+
+impl From<MessageType> for ReceivedKind {
+    fn from(message_type: MessageType) -> Self {
+        use self::MessageType::*;
+        match message_type {
+            Direct => ReceivedKind::Direct,
+            DirectAck => ReceivedKind::DirectAck,
+            DirectNak => ReceivedKind::DirectNak,
+            Broadcast => ReceivedKind::Broadcast,
+            AllLinkBroadcast => ReceivedKind::AllLinkBroadcast,
+            AllLinkCleanup | AllLinkCleanupAck | AllLinkCleanupNak => ReceivedKind::AllLinkCleanup,
+        }
+    }
+}
+
+/// The outcome of an ALL-Link cleanup, as reported by either of the cleanup messages.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CleanupOutcome {
+    /// The cleanup completed.
+    Succeeded,
+    /// The cleanup was aborted due to traffic on the network.
+    AbortedByTraffic,
+    /// The given device in the given group didn't acknowledge the cleanup.
+    Failed { group: Group, address: Address },
 }
 
+impl Message {
+    /// Returns the address of the device this message is about, if there is one.
+    pub fn sender(&self) -> Option<Address> {
+        use self::Message::*;
+        match self {
+            Received(msg) => Some(msg.from),
+            LinkComplete(result) => Some(result.id()),
+            LinkCleanupFailed(_, address) => Some(*address),
+            LinkRecordResponse(_, _, address, _) => Some(*address),
+            DatabaseRecordFound(record) => Some(record.id),
+            X10Received(_) | ButtonEvent(_) | UserResetDetected | LinkCleanupStatus(_) => None,
+        }
+    }
+    /// Encodes this message as the frame the modem sends for it (the inverse of decoding).
+    ///
+    /// Equal messages always encode to the same bytes, so the frame can be hashed or compared to
+    /// spot repeats, or written out to replay a captured message. Decoding the frame gives back
+    /// the same message, except where the message holds something the wire can't: received X10
+    /// messages are always decoded without a status (their frames carry none), invalid X10
+    /// units (see `UnitCode::new`) are written as unit 13 (nibble `0x0`), button events for
+    /// buttons without a code (see `ButtonEvent::to_im_code`) are written as events for the SET
+    /// button, and a cleanup status that isn't a success is written as a NAK.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        use self::Message::*;
+        let (opcode, payload) = match *self {
+            Received(msg) => {
+                let mut payload = Vec::with_capacity(23);
+                payload.extend_from_slice(&<[u8; 3]>::from(msg.from));
+                payload.extend_from_slice(&<[u8; 3]>::from(msg.to));
+                payload.extend_from_slice(&[msg.flags.0, msg.cmd1, msg.cmd2]);
+                match msg.data {
+                    Some(data) => {
+                        payload.extend_from_slice(&data);
+                        (Opcode::ExtendedMessageReceived, payload)
+                    }
+                    None => (Opcode::StandardMessageReceived, payload),
+                }
+            }
+            X10Received(msg) => {
+                let (low, flag) = msg.payload.to_nibble_and_flag().unwrap_or((0x0, 0x00));
+                let raw = msg.house.to_nibble() << 4 | low;
+                (Opcode::X10Received, vec![raw, flag])
+            }
+            LinkComplete(result) => (Opcode::LinkComplete, <[u8; 8]>::from(result).to_vec()),
+            ButtonEvent(event) => {
+                use crate::button::ButtonEvent::*;
+                let code = event.to_im_code().unwrap_or(match event {
+                    Tapped(_) => 0x02,
+                    Held(_) => 0x03,
+                    Released(_) => 0x04,
+                });
+                (Opcode::ButtonEvent, vec![code])
+            }
+            UserResetDetected => (Opcode::UserReset, Vec::new()),
+            LinkCleanupFailed(group, address) => {
+                let [high, middle, low]: [u8; 3] = address.into();
+                (
+                    Opcode::LinkCleanupFailed,
+                    vec![0x01, group, high, middle, low],
+                )
+            }
+            LinkRecordResponse(flags, group, address, data) => {
+                let mut payload = vec![flags, group];
+                payload.extend_from_slice(&<[u8; 3]>::from(address));
+                payload.extend_from_slice(&data.data);
+                (Opcode::LinkRecordResponse, payload)
+            }
+            LinkCleanupStatus(completed) => {
+                let status = if completed { 0x06 } else { 0x15 };
+                (Opcode::LinkCleanupStatus, vec![status])
+            }
+            DatabaseRecordFound(record) => {
+                let mut payload = record.address.to_vec();
+                payload.extend_from_slice(&[record.flags, record.group]);
+                payload.extend_from_slice(&<[u8; 3]>::from(record.id));
+                payload.extend_from_slice(&record.data.data);
+                (Opcode::DatabaseRecordFound, payload)
+            }
+        };
+        frame::encode(opcode, &payload)
+    }
+    /// Returns what kind of message was received, if this is a received message.
+    pub fn received_kind(&self) -> Option<ReceivedKind> {
+        match self {
+            Message::Received(msg) => Some(msg.flags.message_type().into()),
+            _ => None,
+        }
+    }
+    /// Whether this message reports something that happened on its own, rather than in answer to
+    /// something the host did.
+    ///
+    /// Events are button events on the modem, user resets, received X10 messages, and received
+    /// Insteon messages other than ACKs and NAKs (such as a switch's ALL-Link broadcast when it's
+    /// pressed). Every other message is an acknowledgement (see `is_acknowledgement`).
+    pub fn is_event(&self) -> bool {
+        use self::Message::*;
+        match self {
+            ButtonEvent(_) | UserResetDetected | X10Received(_) => true,
+            Received(msg) => !matches!(
+                msg.flags.message_type(),
+                MessageType::DirectAck
+                    | MessageType::DirectNak
+                    | MessageType::AllLinkCleanupAck
+                    | MessageType::AllLinkCleanupNak
+            ),
+            LinkComplete(_)
+            | LinkCleanupFailed(..)
+            | LinkRecordResponse(..)
+            | LinkCleanupStatus(_)
+            | DatabaseRecordFound(_) => false,
+        }
+    }
+    /// Whether this message answers something the host did (such as a device's ACK of a direct
+    /// command, the outcome of linking, or a record read from a database).
+    ///
+    /// This is the opposite of `is_event`.
+    pub fn is_acknowledgement(&self) -> bool {
+        !self.is_event()
+    }
+    /// Whether this message reports that a device didn't acknowledge an ALL-Link cleanup.
+    ///
+    /// This is how unreachable devices show up after a group command.
+    pub fn is_cleanup_failure(&self) -> bool {
+        matches!(self, Message::LinkCleanupFailed(..))
+    }
+    /// Returns the outcome of an ALL-Link cleanup, if this message reports one.
+    pub fn cleanup_outcome(&self) -> Option<CleanupOutcome> {
+        match *self {
+            Message::LinkCleanupStatus(true) => Some(CleanupOutcome::Succeeded),
+            Message::LinkCleanupStatus(false) => Some(CleanupOutcome::AbortedByTraffic),
+            Message::LinkCleanupFailed(group, address) => {
+                Some(CleanupOutcome::Failed { group, address })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Describes the message. The alternate form (`{:#}`) follows the description with the message's
+/// frame (see `to_bytes`) in hex, e.g. `User reset initiated. [02 55]`, for tracing traffic.
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.describe(f)?;
+        if f.alternate() {
+            write!(f, " [")?;
+            for (i, byte) in self.to_bytes().iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{:02X}", byte)?;
+            }
+            write!(f, "]")?;
+        }
+        Ok(())
+    }
+}
+
+impl Message {
+    /// Writes the description of the message used by `Display`.
+    fn describe(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::Message::*;
         match self {
-            Received(_addr, _flags, _cmds, data) => match data {
-                None => write!(f, "Received standard message."),
-                Some(msg) => write!(f, "Received extended message with data: {:x?}", msg),
-            },
+            Received(msg) => {
+                match msg.command() {
+                    Some(command) => write!(f, "Received {}", command)?,
+                    None => write!(f, "Received command {:02X} {:02X}", msg.cmd1, msg.cmd2)?,
+                }
+                write!(f, " from {} ({})", msg.from, msg.flags)?;
+                match msg.data {
+                    Some(data) => write!(f, " with data: {:02X?}", data),
+                    None => Ok(()),
+                }
+            }
             X10Received(msg) => write!(f, "Received X10 result: {}", msg),
-            LinkComplete(_result) => write!(f, "ALL-Link completed (details omitted)."),
+            LinkComplete(result) => write!(f, "ALL-Link completed: {}.", result),
             ButtonEvent(e) => write!(f, "{}", e),
             UserResetDetected => write!(f, "User reset initiated."),
             LinkCleanupFailed(group, address) => write!(
@@ -63,7 +831,7 @@ impl fmt::Display for Message {
                     write!(f, "ALL-Link cleanup aborted due to traffic.")
                 }
             }
-            DatabaseRecordFound(_address, _flags, _group, _id, _link) => {
+            DatabaseRecordFound(_record) => {
                 write!(f, "Database record found (omitted).")
             }
         }
@@ -71,7 +839,12 @@ impl fmt::Display for Message {
 }
 
 /// Encodes a modem configuration.
+///
+/// Only the upper five bits of the configuration byte are meaningful; the remaining bits are
+/// reserved. They're ignored when decoding and always encoded as zero, so a configuration read
+/// from the modem and written back unmodified yields the same byte with the reserved bits cleared.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Config {
     /// Whether linking should be initiated when the user presses and holds the SET button.
     pub auto_link: bool,
@@ -85,24 +858,92 @@ pub struct Config {
     pub busy_reject: bool,
 }
 
-impl Into<u8> for Config {
-    fn into(self) -> u8 {
-        ((!self.auto_link as u8) << 7)
-            | ((self.monitor as u8) << 6)
-            | ((self.manual_led as u8) << 5)
-            | ((!self.timeout as u8) << 4)
-            | ((self.busy_reject as u8) << 3)
+impl Config {
+    /// The bits of the configuration byte represented by this type.
+    pub const MANAGED_BITS: u8 = 0b1111_1000;
+    /// The bit that turns off linking by holding the SET button (the inverse of `auto_link`).
+    pub const DISABLE_AUTO_LINK_BIT: u8 = 0b1000_0000;
+    /// The bit that turns on monitor mode (`monitor`).
+    pub const MONITOR_BIT: u8 = 0b0100_0000;
+    /// The bit that hands the LED over to the host (`manual_led`).
+    pub const MANUAL_LED_BIT: u8 = 0b0010_0000;
+    /// The bit that turns off the 240 ms timeout (the inverse of `timeout`).
+    pub const DISABLE_TIMEOUT_BIT: u8 = 0b0001_0000;
+    /// The bit that makes the modem refuse commands while it's busy (`busy_reject`).
+    pub const BUSY_REJECT_BIT: u8 = 0b0000_1000;
+    /// Decodes a configuration byte (the same as the `From<u8>` conversion).
+    pub fn from_bits(bits: u8) -> Self {
+        bits.into()
+    }
+    /// Encodes the configuration as a byte (the same as the `Into<u8>` conversion).
+    pub fn bits(self) -> u8 {
+        self.into()
+    }
+    /// Returns a builder for a configuration, starting from the default.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+}
+
+/// Builds a modem configuration.
+///
+/// Any flag that isn't set explicitly keeps its default value.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Sets whether linking should be initiated when the user presses and holds the SET button.
+    pub fn auto_link(mut self, auto_link: bool) -> Self {
+        self.config.auto_link = auto_link;
+        self
+    }
+    /// Sets whether the modem is in monitor mode.
+    pub fn monitor(mut self, monitor: bool) -> Self {
+        self.config.monitor = monitor;
+        self
+    }
+    /// Sets whether the LED should be managed by the host (and not the modem).
+    pub fn manual_led(mut self, manual_led: bool) -> Self {
+        self.config.manual_led = manual_led;
+        self
+    }
+    /// Sets whether the modem should timeout after 240 ms.
+    pub fn timeout(mut self, timeout: bool) -> Self {
+        self.config.timeout = timeout;
+        self
+    }
+    /// Sets whether the modem should reject commands (NAK) if it's busy processing.
+    pub fn busy_reject(mut self, busy_reject: bool) -> Self {
+        self.config.busy_reject = busy_reject;
+        self
+    }
+    /// Returns the built configuration.
+    pub fn build(self) -> Config {
+        self.config
+    }
+}
+
+impl From<Config> for u8 {
+    fn from(config: Config) -> Self {
+        let bit = |set: bool, bit: u8| if set { bit } else { 0 };
+        bit(!config.auto_link, Config::DISABLE_AUTO_LINK_BIT)
+            | bit(config.monitor, Config::MONITOR_BIT)
+            | bit(config.manual_led, Config::MANUAL_LED_BIT)
+            | bit(!config.timeout, Config::DISABLE_TIMEOUT_BIT)
+            | bit(config.busy_reject, Config::BUSY_REJECT_BIT)
     }
 }
 
 impl From<u8> for Config {
     fn from(byte: u8) -> Self {
         Self {
-            auto_link: byte & 0b1000_0000 == 0,
-            monitor: byte & 0b0100_0000 == 0b0100_0000,
-            manual_led: byte & 0b0010_0000 == 0b0010_0000,
-            timeout: byte & 0b0001_0000 == 0,
-            busy_reject: byte & 0b0000_1000 == 0b0000_1000,
+            auto_link: byte & Config::DISABLE_AUTO_LINK_BIT == 0,
+            monitor: byte & Config::MONITOR_BIT != 0,
+            manual_led: byte & Config::MANUAL_LED_BIT != 0,
+            timeout: byte & Config::DISABLE_TIMEOUT_BIT == 0,
+            busy_reject: byte & Config::BUSY_REJECT_BIT != 0,
         }
     }
 }
@@ -120,25 +961,55 @@ impl Default for Config {
     }
 }
 
+impl fmt::Display for Config {
+    /// Lists the enabled flags, e.g. `Config(auto_link, monitor, timeout)`.
+    ///
+    /// The default configuration is shown as `Config(default)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if *self == Config::default() {
+            return write!(f, "Config(default)");
+        }
+        let flags = [
+            ("auto_link", self.auto_link),
+            ("monitor", self.monitor),
+            ("manual_led", self.manual_led),
+            ("timeout", self.timeout),
+            ("busy_reject", self.busy_reject),
+        ];
+        let enabled = flags.iter().filter(|(_, enabled)| *enabled);
+        write!(f, "Config(")?;
+        for (i, (name, _)) in enabled.enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", name)?;
+        }
+        write!(f, ")")
+    }
+}
+
 /// Responses are delivered from the modem to us in response to issued commands.
 ///
 /// They therefore differ in significance from messages because we request and expect them.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Response {
     /// The device info was retrieved.
-    GotInfo(Address, [u8; 2], Option<u8>),
+    GotInfo(Address, Category, Option<Firmware>),
     /// The requested link command was sent to the group.
     SentLinkCommand(Group, u8, u8),
-    // TODO: Fix this
-    SentMessage(Message),
+    /// The message was sent to the given address with the given flags, command bytes, and (for
+    /// extended messages) user data.
+    SentMessage(Address, MessageFlags, [u8; 2], Option<[u8; 14]>),
     /// The requested X10 message was sent.
     SentX10(X10Message),
-    StartedLink(u8, Group),
+    /// The modem started linking in the given role for the given group.
+    StartedLink(LinkRole, Group),
     CanceledLink,
     /// The host device category (and subcategory) were successfully set.
     ///
     /// If applicable, this command also returns the firmware version.
-    SetCategory([u8; 2], Option<u8>),
+    SetCategory(Category, Option<Firmware>),
     /// The modem was successfully reset to factory settings, wiping the ALL-Link database.
     Reset,
     /// The ACK byte (`0x06`) will be followed by the requested (and returned) byte.
@@ -161,7 +1032,10 @@ pub enum Response {
     /// The LED was turned off.
     LedOff,
     /// The specified ALL-Link record was inserted into the database.
-    UpdatedLinkRecord(u8, u8, Group, Address, [u8; 3]),
+    ///
+    /// The fields are the operation, the record control flags, the group, the linked device, and
+    /// the link data.
+    UpdatedLinkRecord(LinkRecordControl, u8, Group, Address, [u8; 3]),
     /// The NAK byte (`0x15`) will be followed by the requested (and returned) byte.
     SetNakByte(u8),
     /// The ACK byte (`0x06`) will be followed by the requested (and returned) bytes.
@@ -170,13 +1044,14 @@ pub enum Response {
     SetAckBytes([u8; 2]),
     /// The RF modem was put to sleep and will wake again when sent a byte.
     Sleeping,
-    /// Returns the configuration flags for the modem.
+    /// Returns the configuration flags for the modem, followed by the two bytes that accompany
+    /// them.
     ///
     /// ## Notes
-    /// There are two bytes of reserved data that come in this response as well, but this
-    /// implementation ignores them, returning only the first field (the configuration byte),
-    /// parsed into a convenient format.
-    GotConfig(Config),
+    /// The two trailing bytes are documented as reserved, but some firmware uses the first of
+    /// them to report the ALL-Link database delta (which changes whenever the database does), so
+    /// they're passed along as-is.
+    GotConfig(Config, u8, u8),
 
     // The following commands were added to the spec after the initial release.
     /// ALL-Link cleanup was successfully canceled.
@@ -186,11 +1061,13 @@ pub enum Response {
     ReadDatabaseBytes([u8; 2]),
     /// The device will beep.
     Beeping,
+    /// The modem status byte was set as requested.
     SetStatus(u8),
 
     // The following commands are RF modem-only.
     // It is left unclear whether they are in the initial version of the spec,
     // but looking at their numbering, let's assume not.
+    /// The link data for the next ALL-Link was set as specified.
     SetLinkData([u8; 3]),
     /// The number of application retries for new links was set as specified.
     SetRetries(u8),
@@ -203,6 +1080,21 @@ pub enum Response {
     SetTempLincAck(u8),
 }
 
+impl Response {
+    /// Returns the ALL-Link database delta, if this is a configuration response (see
+    /// `GotConfig`).
+    ///
+    /// The delta is the first of the two bytes after the configuration flags. Firmware that
+    /// doesn't report it leaves it zero, so a delta that never changes is no proof that the
+    /// database hasn't.
+    pub fn database_delta(&self) -> Option<u8> {
+        match *self {
+            Response::GotConfig(_, delta, _) => Some(delta),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -211,9 +1103,173 @@ mod tests {
         let cfg = Config::default();
         let byte: u8 = cfg.into();
         assert_eq!(byte, 0);
-        let mut cfg = Config::default();
-        cfg.timeout = false;
+        let cfg = Config {
+            timeout: false,
+            ..Config::default()
+        };
         let byte: u8 = cfg.into();
         assert!(byte != 0);
     }
+    #[test]
+    fn config_round_trip() {
+        for byte in 0..=u8::MAX {
+            let cfg = Config::from(byte);
+            let encoded: u8 = cfg.into();
+            assert_eq!(encoded & !Config::MANAGED_BITS, 0);
+            assert_eq!(encoded & Config::MANAGED_BITS, byte & Config::MANAGED_BITS);
+            assert_eq!(Config::from(encoded), cfg);
+        }
+    }
+    #[test]
+    fn inverted_config_bits() {
+        // A clear bit means auto-linking and the timeout are on.
+        let cfg = Config::from(0);
+        assert!(cfg.auto_link && cfg.timeout);
+        let cfg = Config::from(Config::DISABLE_AUTO_LINK_BIT | Config::DISABLE_TIMEOUT_BIT);
+        assert!(!cfg.auto_link && !cfg.timeout);
+        for &(auto_link, timeout) in &[(false, false), (false, true), (true, false), (true, true)] {
+            let cfg = Config {
+                auto_link,
+                timeout,
+                ..Config::default()
+            };
+            let byte = u8::from(cfg);
+            assert_eq!(byte & Config::DISABLE_AUTO_LINK_BIT == 0, auto_link);
+            assert_eq!(byte & Config::DISABLE_TIMEOUT_BIT == 0, timeout);
+            assert_eq!(Config::from(byte), cfg);
+        }
+    }
+    #[test]
+    fn config_bits() {
+        let cfg = Config::from_bits(Config::MONITOR_BIT | Config::DISABLE_TIMEOUT_BIT);
+        assert!(cfg.monitor && cfg.auto_link && !cfg.timeout);
+        assert_eq!(
+            cfg.bits(),
+            Config::MONITOR_BIT | Config::DISABLE_TIMEOUT_BIT
+        );
+        let bits = [
+            Config::DISABLE_AUTO_LINK_BIT,
+            Config::MONITOR_BIT,
+            Config::MANUAL_LED_BIT,
+            Config::DISABLE_TIMEOUT_BIT,
+            Config::BUSY_REJECT_BIT,
+        ];
+        assert_eq!(
+            bits.iter().fold(0, |all, bit| all | bit),
+            Config::MANAGED_BITS
+        );
+    }
+    #[test]
+    fn operating_flags() {
+        let flags = OperatingFlags {
+            led_off: true,
+            ..OperatingFlags::default()
+        };
+        let codes = flags
+            .messages()
+            .iter()
+            .map(|msg| msg.command.payload())
+            .collect::<Vec<_>>();
+        assert_eq!(codes, [0x01, 0x03, 0x05, 0x08, 0x0B]);
+        let (command, data) = flags.messages()[3].into();
+        assert_eq!(command, Command::SetOperatingFlags(0x08));
+        assert_eq!(data[13], 0xD8);
+    }
+    #[test]
+    fn message_size() {
+        // Messages are copied around freely, so they should stay small.
+        assert!(core::mem::size_of::<Message>() <= 24);
+    }
+    #[test]
+    fn opcode_round_trip() {
+        for byte in 0..=u8::MAX {
+            assert_eq!(u8::from(Opcode::from(byte)), byte);
+        }
+        assert_eq!(Opcode::from(0x76), Opcode::Unknown(0x76));
+    }
+    #[test]
+    fn events() {
+        let mut msg = Received {
+            from: [0x1A, 0x2B, 0x3C].into(),
+            to: [0x00, 0x00, 0x01].into(),
+            flags: MessageFlags(0xCF),
+            cmd1: 0x11,
+            cmd2: 0x01,
+            data: None,
+        };
+        assert!(Message::Received(msg).is_event());
+        msg.flags = MessageFlags(0x2F);
+        assert!(Message::Received(msg).is_acknowledgement());
+        assert!(Message::UserResetDetected.is_event());
+        assert!(Message::LinkCleanupStatus(true).is_acknowledgement());
+    }
+    #[test]
+    fn received_display() {
+        let msg = Message::Received(Received {
+            from: [0x1A, 0x2B, 0x3C].into(),
+            to: [0x00, 0x00, 0x01].into(),
+            flags: MessageFlags(0xCF),
+            cmd1: 0x11,
+            cmd2: 0x01,
+            data: None,
+        });
+        assert_eq!(
+            msg.to_string(),
+            "Received On(group 1) from 1A.2B.3C (ALL-Link broadcast, 3/3 hops left)"
+        );
+        assert_eq!(
+            format!("{:#}", msg),
+            "Received On(group 1) from 1A.2B.3C (ALL-Link broadcast, 3/3 hops left) \
+             [02 50 1A 2B 3C 00 00 01 CF 11 01]"
+        );
+    }
+    #[test]
+    fn received_on_level() {
+        let msg = Received {
+            from: [0x1A, 0x2B, 0x3C].into(),
+            to: [0x11, 0x22, 0x33].into(),
+            flags: MessageFlags(0x0F),
+            cmd1: 0x11,
+            cmd2: 0xFF,
+            data: None,
+        };
+        let level = Command::On(OnPayload::OnLevel(OnLevel(0xFF)));
+        assert_eq!(msg.command(), Some(level));
+    }
+    #[test]
+    fn received_data_bytes() {
+        let mut msg = Received {
+            from: [0x1A, 0x2B, 0x3C].into(),
+            to: [0x11, 0x22, 0x33].into(),
+            flags: MessageFlags(0x1F),
+            cmd1: 0x2E,
+            cmd2: 0x00,
+            data: None,
+        };
+        assert_eq!(msg.command_sub(), None);
+        msg.data = Some([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]);
+        assert_eq!(msg.command_sub(), Some(1));
+        assert_eq!(msg.d(14), Some(14));
+        assert_eq!(msg.d(0), None);
+        assert_eq!(msg.d(15), None);
+        assert_eq!(msg.has_valid_checksum(), Some(false));
+        msg.data = Some(ExtendedMessage::extended_get(1).data);
+        assert_eq!(msg.has_valid_checksum(), Some(true));
+    }
+    #[test]
+    fn extended_checksum() {
+        let msg = ExtendedMessage::read_database_record(0x0FFF);
+        assert_eq!(msg.data[..5], [0x00, 0x00, 0x0F, 0xFF, 0x01]);
+        let sum = [0x2F, 0x00]
+            .iter()
+            .chain(msg.data.iter())
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        assert_eq!(sum, 0);
+    }
+    #[test]
+    fn config_display() {
+        assert_eq!(Config::default().to_string(), "Config(default)");
+        let cfg = Config::builder().monitor(true).timeout(false).build();
+        assert_eq!(cfg.to_string(), "Config(auto_link, monitor)");
+    }
 }
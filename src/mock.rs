@@ -0,0 +1,171 @@
+//! An in-memory stand-in for a serial port, for tests.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::transport::ModemTransport;
+use serialport::{
+    ClearBuffer, DataBits, Error, ErrorKind, FlowControl, Parity, Result, SerialPort,
+    SerialPortSettings, StopBits,
+};
+
+/// A port that reads from a fixed buffer of bytes and records whatever is written to it.
+#[derive(Debug, Default)]
+pub struct MockPort {
+    /// The bytes waiting to be read (in a cell, since `SerialPort::clear` takes `&self`).
+    input: RefCell<VecDeque<u8>>,
+    /// The bytes written so far.
+    pub output: Vec<u8>,
+    /// Bytes to make available for reading once the given bytes have been written.
+    replies: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl MockPort {
+    /// Creates a port with the given bytes waiting to be read.
+    pub fn new(input: &[u8]) -> Self {
+        Self {
+            input: RefCell::new(input.iter().cloned().collect()),
+            output: Vec::new(),
+            replies: Vec::new(),
+        }
+    }
+    /// Makes the given reply available for reading once the given bytes have been written (as
+    /// the modem answers a command only once it's sent).
+    pub fn reply_to(&mut self, written: &[u8], reply: &[u8]) {
+        self.replies.push((written.to_vec(), reply.to_vec()));
+    }
+    /// Adds bytes to be read after the ones already waiting.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.input.get_mut().extend(bytes);
+    }
+    /// The number of bytes waiting to be read.
+    pub fn remaining(&self) -> usize {
+        self.input.borrow().len()
+    }
+}
+
+impl Read for MockPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let input = self.input.get_mut();
+        let n = buf.len().min(input.len());
+        for (byte, input) in buf.iter_mut().zip(input.drain(..n)) {
+            *byte = input;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.extend_from_slice(buf);
+        let output = &self.output;
+        let input = self.input.get_mut();
+        self.replies.retain(|(written, reply)| {
+            let answered = output.ends_with(written);
+            if answered {
+                input.extend(reply);
+            }
+            !answered
+        });
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ModemTransport for MockPort {
+    fn bytes_to_read(&mut self) -> io::Result<usize> {
+        Ok(self.remaining())
+    }
+    fn clear_input(&mut self) -> io::Result<()> {
+        self.input.get_mut().clear();
+        Ok(())
+    }
+    fn try_clone(&self) -> io::Result<Box<dyn ModemTransport>> {
+        Err(io::Error::other("mock ports can't be cloned"))
+    }
+}
+
+impl SerialPort for MockPort {
+    fn name(&self) -> Option<String> {
+        None
+    }
+    fn settings(&self) -> SerialPortSettings {
+        SerialPortSettings::default()
+    }
+    fn baud_rate(&self) -> Result<u32> {
+        Ok(19_200)
+    }
+    fn data_bits(&self) -> Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+    fn flow_control(&self) -> Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+    fn parity(&self) -> Result<Parity> {
+        Ok(Parity::None)
+    }
+    fn stop_bits(&self) -> Result<StopBits> {
+        Ok(StopBits::One)
+    }
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+    fn set_all(&mut self, _: &SerialPortSettings) -> Result<()> {
+        Ok(())
+    }
+    fn set_baud_rate(&mut self, _: u32) -> Result<()> {
+        Ok(())
+    }
+    fn set_data_bits(&mut self, _: DataBits) -> Result<()> {
+        Ok(())
+    }
+    fn set_flow_control(&mut self, _: FlowControl) -> Result<()> {
+        Ok(())
+    }
+    fn set_parity(&mut self, _: Parity) -> Result<()> {
+        Ok(())
+    }
+    fn set_stop_bits(&mut self, _: StopBits) -> Result<()> {
+        Ok(())
+    }
+    fn set_timeout(&mut self, _: Duration) -> Result<()> {
+        Ok(())
+    }
+    fn write_request_to_send(&mut self, _: bool) -> Result<()> {
+        Ok(())
+    }
+    fn write_data_terminal_ready(&mut self, _: bool) -> Result<()> {
+        Ok(())
+    }
+    fn read_clear_to_send(&mut self) -> Result<bool> {
+        Ok(true)
+    }
+    fn read_data_set_ready(&mut self) -> Result<bool> {
+        Ok(true)
+    }
+    fn read_ring_indicator(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+    fn read_carrier_detect(&mut self) -> Result<bool> {
+        Ok(true)
+    }
+    fn bytes_to_read(&self) -> Result<u32> {
+        Ok(self.remaining() as u32)
+    }
+    fn bytes_to_write(&self) -> Result<u32> {
+        Ok(0)
+    }
+    fn clear(&self, buffer: ClearBuffer) -> Result<()> {
+        if let ClearBuffer::Input | ClearBuffer::All = buffer {
+            self.input.borrow_mut().clear();
+        }
+        Ok(())
+    }
+    fn try_clone(&self) -> Result<Box<dyn SerialPort>> {
+        Err(Error::new(ErrorKind::Unknown, "mock ports can't be cloned"))
+    }
+}
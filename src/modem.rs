@@ -0,0 +1,761 @@
+//! A high-level interface to the modem.
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::command::{BrightDim, Command, GroupNumber, OnLevel};
+use crate::device::{Address, Category, DeviceInfo, EngineVersion, Firmware};
+use crate::message::{
+    Group, LinkResult, LinkRole, Message, MessageFlags, MessageType, OperatingFlags, Received,
+};
+use crate::port::PortError;
+use crate::serial::{self, ChecksumMode, Reader, Result, SerialError, Writer};
+use crate::transport::ModemTransport;
+use crate::x10::{self, HouseCode, Payload, UnitCode};
+
+/// Standard direct message flags, with the maximum number of hops.
+const DIRECT: MessageFlags = MessageFlags(0x0F);
+
+/// How long to wait for a reply before giving up, unless otherwise specified.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How many times to try stopping a ramp before giving up, so a dimmer isn't left ramping.
+const STOP_ATTEMPTS: usize = 3;
+
+/// The outcome of a scene command (see `Modem::scene_on`).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SceneReport {
+    /// The members of the group that acknowledged the command.
+    pub succeeded: Vec<Address>,
+    /// The members of the group that didn't acknowledge the command.
+    pub failed: Vec<Address>,
+    /// Whether the modem finished following up with every member.
+    ///
+    /// This is false if the modem gave up early (because of other traffic) or didn't report back
+    /// in time.
+    pub completed: bool,
+}
+
+/// A modem attached to a serial port.
+///
+/// This wraps the low-level utilities in `serial`, remembering what it learns about other devices
+/// along the way.
+pub struct Modem {
+    port: Box<dyn ModemTransport>,
+    name: Option<OsString>,
+    auto_reconnect: Option<(usize, Duration)>,
+    engines: HashMap<Address, EngineVersion>,
+    delta: Option<u8>,
+    /// The modem's own address, once it's known.
+    address: Option<Address>,
+    /// The database delta at which the ALL-Link records were counted, along with their number.
+    link_count: Option<(u8, usize)>,
+    timeout: Duration,
+    checksum_mode: ChecksumMode,
+}
+
+impl Modem {
+    /// Creates a modem communicating over the given port (or other transport).
+    pub fn new<T: ModemTransport + 'static>(port: T) -> Self {
+        Self {
+            port: Box::new(port),
+            name: None,
+            auto_reconnect: None,
+            engines: HashMap::new(),
+            delta: None,
+            address: None,
+            link_count: None,
+            timeout: DEFAULT_TIMEOUT,
+            checksum_mode: ChecksumMode::Off,
+        }
+    }
+    /// Opens the named serial port and creates a modem communicating over it.
+    ///
+    /// The name is remembered so that the port can be reopened later (see `reconnect`).
+    pub fn open<S: AsRef<OsStr>>(name: S) -> std::result::Result<Self, PortError> {
+        let mut modem = crate::port::open(&name).map(Self::new)?;
+        modem.name = Some(name.as_ref().to_os_string());
+        Ok(modem)
+    }
+    /// The underlying port, for use with the utilities in `serial`.
+    pub fn port(&mut self) -> &mut dyn ModemTransport {
+        &mut *self.port
+    }
+    /// Splits the modem into a reading half and a writing half, so that messages can be read on
+    /// one thread while commands are sent from another.
+    ///
+    /// Both halves use the same port, by way of `ModemTransport::try_clone`; this fails wherever
+    /// cloning isn't supported (which depends on the platform and kind of port). What the modem
+    /// has learned about other devices (such as their engine versions) is discarded.
+    pub fn split(self) -> io::Result<(Reader, Writer)> {
+        let writer = self.port.try_clone()?;
+        Ok((Reader::new(self.port), Writer::new(writer)))
+    }
+    /// Writes out anything buffered for the modem but not yet sent.
+    pub fn flush(&mut self) -> Result<()> {
+        let flushed = serial::flush(&mut *self.port);
+        self.recover(flushed)
+    }
+    /// Discards everything waiting to be read from the modem (see `serial::clear_input`).
+    pub fn clear_input(&mut self) -> Result<()> {
+        let cleared = serial::clear_input(&mut *self.port);
+        self.recover(cleared)
+    }
+    /// Brings the modem's port into alignment with the frames arriving on it (see `serial::sync`).
+    ///
+    /// Call this once after opening the port, so that bytes left over from before it was opened
+    /// aren't mistaken for the start of a frame.
+    pub fn sync(&mut self) -> Result<()> {
+        let synced = serial::sync(&mut *self.port, self.timeout);
+        self.recover(synced)
+    }
+    /// How long to wait for a reply (from the modem or another device) before giving up.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+    /// Sets how long to wait for a reply before giving up.
+    ///
+    /// The default is two seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+    /// Whether the checksums of extended replies are checked.
+    pub fn checksum_mode(&self) -> ChecksumMode {
+        self.checksum_mode
+    }
+    /// Sets whether the checksums of extended replies are checked.
+    ///
+    /// By default, they aren't. With `ChecksumMode::VerifyKnownEngines`, only replies from devices
+    /// whose engine version has been looked up (see `engine_version`) are checked.
+    pub fn set_checksum_mode(&mut self, mode: ChecksumMode) {
+        self.checksum_mode = mode;
+    }
+    /// Gets the modem's address, category, and firmware version.
+    pub fn get_info(&mut self) -> Result<(Address, Category, Option<Firmware>)> {
+        let info = serial::get_info(&mut *self.port, self.timeout);
+        let info = self.recover(info)?;
+        self.address = Some(info.0);
+        Ok(info)
+    }
+    /// Gets the modem's own address.
+    ///
+    /// The modem is only asked the first time (unless `get_info` already has); the address is
+    /// remembered thereafter.
+    pub fn address(&mut self) -> Result<Address> {
+        match self.address {
+            Some(address) => Ok(address),
+            None => self.get_info().map(|(address, ..)| address),
+        }
+    }
+    /// Whether the given address is the modem's own (as with the messages the modem sends, when
+    /// they're heard repeated by other devices).
+    pub fn is_self(&mut self, address: Address) -> Result<bool> {
+        Ok(self.address()? == address)
+    }
+    /// Gets the version of the Insteon engine the given device runs.
+    ///
+    /// The device is only queried the first time; the result is remembered thereafter.
+    pub fn engine_version(&mut self, address: Address) -> Result<EngineVersion> {
+        if let Some(&version) = self.engines.get(&address) {
+            return Ok(version);
+        }
+        let reply =
+            self.send_and_await_reply(address, Command::EngineVersionRequest, None, self.timeout)?;
+        // I2CS devices NAK (with 0xFD) requests from modems they aren't linked to.
+        let version = match reply.flags.message_type() {
+            MessageType::DirectNak if reply.cmd2 == 0xFD => EngineVersion::I2cs,
+            MessageType::DirectNak => return Err(SerialError::Nak),
+            _ => EngineVersion::from(reply.cmd2),
+        };
+        self.engines.insert(address, version);
+        Ok(version)
+    }
+    /// Asks the given device to identify itself, waiting up to the given timeout for its answer.
+    ///
+    /// The device acknowledges the ID request and then sends a SET button pressed broadcast,
+    /// whose "to" address carries its category, subcategory, and firmware version. Its engine
+    /// version is looked up as well (see `engine_version`).
+    pub fn identify(&mut self, address: Address, timeout: Duration) -> Result<DeviceInfo> {
+        let deadline = Instant::now() + timeout;
+        self.send_and_await_ack(address, Command::IdRequest, timeout)?;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let broadcast = await_set_button_broadcast(&mut *self.port, address, remaining);
+        let (category, firmware, _) = self.recover(broadcast)?;
+        let engine = self.engine_version(address)?;
+        Ok(DeviceInfo {
+            address,
+            category,
+            firmware: Firmware::from_raw(firmware),
+            engine,
+        })
+    }
+    /// Sends an X10 command to the given unit, as an address frame followed directly by a command
+    /// frame, waiting for the modem to echo each.
+    ///
+    /// If the address frame fails, the command frame isn't sent.
+    pub fn send_x10(
+        &mut self,
+        house: HouseCode,
+        unit: UnitCode,
+        command: x10::Command,
+    ) -> Result<()> {
+        let timeout = self.timeout;
+        for &payload in &[Payload::UnitCode(unit), Payload::Command(command)] {
+            let sent = serial::send_x10(&mut *self.port, house, payload, timeout);
+            self.recover(sent)?;
+        }
+        Ok(())
+    }
+    /// Turns on the scene for the given group, reporting which members acknowledged it.
+    ///
+    /// The group command is broadcast, and the modem then follows up with each member of the
+    /// group (an ALL-Link cleanup). Reports are collected until the modem says it's done or the
+    /// timeout (see `set_timeout`) passes without it, whichever comes first.
+    pub fn scene_on(&mut self, group: Group) -> Result<SceneReport> {
+        self.scene(group, Command::on_group(group))
+    }
+    /// Turns off the scene for the given group, as in `scene_on`.
+    pub fn scene_off(&mut self, group: Group) -> Result<SceneReport> {
+        self.scene(group, Command::Off(Some(GroupNumber(group))))
+    }
+    /// Sends the given command to the given group and collects the cleanup reports.
+    fn scene(&mut self, group: Group, command: Command) -> Result<SceneReport> {
+        let sent = serial::send_link_command(&mut *self.port, group, command, self.timeout);
+        self.recover(sent)?;
+        let report = await_cleanup(&mut *self.port, group, command, self.timeout);
+        self.recover(report)
+    }
+    /// Waits for a message matching the given predicate, giving up after the given timeout.
+    ///
+    /// Messages that don't match are discarded, as are responses. This is the building block of
+    /// the methods that send a command and wait for a device to answer; pair it with
+    /// `serial::send_message` (on `port`) for flows this type doesn't cover.
+    pub fn wait_for<F: Fn(&Message) -> bool>(
+        &mut self,
+        predicate: F,
+        timeout: Duration,
+    ) -> Result<Message> {
+        let found = wait_for_map(&mut *self.port, timeout, |msg| {
+            if predicate(msg) {
+                Some(*msg)
+            } else {
+                None
+            }
+        });
+        self.recover(found)
+    }
+    /// Puts the modem into linking mode in the given role for the given group, then waits up to
+    /// the given timeout for a device to link (such as when its SET button is pressed).
+    ///
+    /// If no device links in time, linking is canceled and `SerialError::LinkTimeout` is
+    /// returned, so the user can be asked to try again.
+    pub fn link_device(
+        &mut self,
+        role: LinkRole,
+        group: Group,
+        timeout: Duration,
+    ) -> Result<LinkResult> {
+        // Even a failed attempt may have linked a device before it failed.
+        self.link_count = None;
+        let linked = link_device(&mut *self.port, role, group, timeout, self.timeout);
+        self.recover(linked)
+    }
+    /// Sends a direct command to the given device and waits for the device to acknowledge it.
+    ///
+    /// Unlike `serial::send_message`, which only confirms that the modem sent the command, this
+    /// confirms that the device received it. Unrelated traffic that arrives in the meantime is
+    /// discarded, and a NAK from the device is reported as an error.
+    pub fn send_and_await_ack(
+        &mut self,
+        address: Address,
+        command: Command,
+        timeout: Duration,
+    ) -> Result<Message> {
+        let reply = self.send_and_await_reply(address, command, None, timeout)?;
+        match reply.flags.message_type() {
+            MessageType::DirectNak => Err(SerialError::Nak),
+            _ => Ok(Message::Received(reply)),
+        }
+    }
+    /// Pings the given device, returning how long it took to acknowledge the ping.
+    ///
+    /// If the device doesn't answer within the timeout, `SerialError::Timeout` is returned.
+    pub fn ping(&mut self, address: Address, timeout: Duration) -> Result<Duration> {
+        let start = Instant::now();
+        self.send_and_await_ack(address, Command::Ping, timeout)?;
+        Ok(start.elapsed())
+    }
+    /// Turns the given device on to the given level, waiting for it to acknowledge the command.
+    ///
+    /// On and off commands are standard messages, so no checksum is needed (even for I2CS
+    /// devices, which only require one on extended messages).
+    pub fn turn_on(&mut self, address: Address, level: OnLevel) -> Result<()> {
+        self.send_and_await_ack(address, Command::on_level(level.0), self.timeout)
+            .map(|_| ())
+    }
+    /// Turns the given device off, waiting for it to acknowledge the command.
+    pub fn turn_off(&mut self, address: Address) -> Result<()> {
+        self.send_and_await_ack(address, Command::Off(None), self.timeout)
+            .map(|_| ())
+    }
+    /// Gets the given device's current on level (0–255).
+    pub fn get_status(&mut self, address: Address) -> Result<u8> {
+        let reply =
+            self.send_and_await_reply(address, Command::StatusRequest(0x00), None, self.timeout)?;
+        match reply.flags.message_type() {
+            MessageType::DirectNak => Err(SerialError::Nak),
+            _ => Ok(reply.cmd2),
+        }
+    }
+    /// Ramps the given dimmer in the given direction for the given duration, returning its level
+    /// afterward (if it reports one).
+    ///
+    /// If the device doesn't acknowledge the command to stop ramping, it's sent again (a few
+    /// times) before the error is returned.
+    pub fn ramp(
+        &mut self,
+        address: Address,
+        direction: BrightDim,
+        duration: Duration,
+    ) -> Result<Option<u8>> {
+        self.send_and_await_ack(address, Command::Start(direction), self.timeout)?;
+        thread::sleep(duration);
+        let mut stopped = self.send_and_await_ack(address, Command::Stop, self.timeout);
+        for _ in 1..STOP_ATTEMPTS {
+            if stopped.is_ok() {
+                break;
+            }
+            stopped = self.send_and_await_ack(address, Command::Stop, self.timeout);
+        }
+        stopped?;
+        Ok(self.get_status(address).ok())
+    }
+    /// Sets the given device's operating flags, waiting for it to acknowledge each one.
+    pub fn set_operating_flags(&mut self, address: Address, flags: OperatingFlags) -> Result<()> {
+        for message in flags.messages().iter() {
+            let reply = self.send_and_await_reply(
+                address,
+                message.command,
+                Some(message.data),
+                self.timeout,
+            )?;
+            if reply.flags.message_type() == MessageType::DirectNak {
+                return Err(SerialError::Nak);
+            }
+        }
+        Ok(())
+    }
+    /// Sends a direct command (with user data, for extended messages) to the given device and
+    /// waits for its ACK or NAK.
+    fn send_and_await_reply(
+        &mut self,
+        address: Address,
+        command: Command,
+        data: Option<[u8; 14]>,
+        timeout: Duration,
+    ) -> Result<Received> {
+        let reply = await_reply(&mut *self.port, address, command, data, timeout);
+        let reply = self.recover(reply)?;
+        let engine = self.engines.get(&address).cloned();
+        self.checksum_mode.check(&reply, engine)?;
+        Ok(reply)
+    }
+    /// Reopens the serial port the modem was opened with (by name), with the same settings
+    /// `open` uses.
+    ///
+    /// This is handy after a USB serial adapter has been unplugged and plugged back in.
+    pub fn reconnect(&mut self) -> std::result::Result<(), PortError> {
+        let name = match self.name {
+            Some(ref name) => name,
+            None => {
+                return Err(PortError::Other(serialport::Error::new(
+                    serialport::ErrorKind::NoDevice,
+                    "The modem wasn't opened by name",
+                )))
+            }
+        };
+        self.port = Box::new(crate::port::open(name)?);
+        Ok(())
+    }
+    /// Makes the modem try to reconnect (see `reconnect`) whenever an I/O error occurs, trying up
+    /// to the given number of times and waiting the given delay before each attempt.
+    ///
+    /// The operation that failed still returns its error (it may have partially taken effect, so
+    /// it isn't retried), but the next one uses the reopened port.
+    pub fn with_auto_reconnect(mut self, retries: usize, delay: Duration) -> Self {
+        self.auto_reconnect = Some((retries, delay));
+        self
+    }
+    /// Reconnects (if configured to) when the given result is an I/O error, then returns it.
+    fn recover<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let (Err(SerialError::Io(_)), Some((retries, delay))) = (&result, self.auto_reconnect) {
+            for _ in 0..retries {
+                thread::sleep(delay);
+                if self.reconnect().is_ok() {
+                    break;
+                }
+            }
+        }
+        result
+    }
+    /// The number of records in the modem's ALL-Link database.
+    ///
+    /// Counting means walking the whole database, so the count is remembered along with the
+    /// database delta (see `has_database_changed`) and only redone once the delta changes, or
+    /// once the database is changed through this type (by `link_device`). A delta of zero is
+    /// what firmware that doesn't report one leaves there, so the count is never remembered at a
+    /// zero delta. Changes made through `port` aren't noticed unless the delta changes.
+    pub fn link_count(&mut self) -> Result<usize> {
+        let delta = serial::database_delta(&mut *self.port, self.timeout);
+        let delta = self.recover(delta)?;
+        if let Some((counted_at, count)) = self.link_count {
+            if counted_at == delta && delta != 0 {
+                return Ok(count);
+            }
+        }
+        let records = serial::read_link_records(&mut *self.port, self.timeout);
+        let count = self.recover(records)?.len();
+        self.link_count = Some((delta, count));
+        Ok(count)
+    }
+
+    /// Whether the modem's ALL-Link database has changed since this was last called.
+    ///
+    /// This compares the database delta (see `serial::database_delta`) against the value seen
+    /// last time, so it's much cheaper than reading the whole database. The first call always
+    /// returns `true`, since there's nothing to compare against.
+    pub fn has_database_changed(&mut self) -> Result<bool> {
+        let delta = serial::database_delta(&mut *self.port, self.timeout);
+        let delta = self.recover(delta)?;
+        let changed = self.delta != Some(delta);
+        self.delta = Some(delta);
+        Ok(changed)
+    }
+}
+
+/// Sends a direct command to the given device and waits for its ACK or NAK.
+fn await_reply(
+    port: &mut dyn ModemTransport,
+    address: Address,
+    command: Command,
+    data: Option<[u8; 14]>,
+    timeout: Duration,
+) -> Result<Received> {
+    let deadline = Instant::now() + timeout;
+    serial::send_message(port, address, DIRECT, command, data, timeout)?;
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    wait_for_map(port, remaining, |msg| {
+        let msg = match *msg {
+            Message::Received(msg) => msg,
+            _ => return None,
+        };
+        // Replies to status requests carry the device's ALL-Link database delta in place of the
+        // command.
+        let is_reply = matches!(command, Command::StatusRequest(_)) || msg.cmd1 == command.opcode();
+        match msg.flags.message_type() {
+            MessageType::DirectAck | MessageType::DirectNak if msg.from == address && is_reply => {
+                Some(msg)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Reads messages until the given function picks something out of one, giving up after the
+/// given timeout. The messages passed over are discarded.
+fn wait_for_map<T, F: FnMut(&Message) -> Option<T>>(
+    port: &mut dyn ModemTransport,
+    timeout: Duration,
+    mut f: F,
+) -> Result<T> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if let Some(found) = f(&serial::next_message_timeout(port, remaining)?) {
+            return Ok(found);
+        }
+    }
+}
+
+/// Links a device as in `Modem::link_device`, using the given timeout for the modem's responses.
+fn link_device(
+    port: &mut dyn ModemTransport,
+    role: LinkRole,
+    group: Group,
+    timeout: Duration,
+    response_timeout: Duration,
+) -> Result<LinkResult> {
+    let mut session = serial::begin_linking_session(port, role, group, response_timeout)?;
+    match session.wait_for_completion(timeout) {
+        Err(SerialError::Timeout { .. }) => {
+            session.cancel()?;
+            Err(SerialError::LinkTimeout)
+        }
+        result => result,
+    }
+}
+
+/// Collects the cleanup reports for the given group and command until the modem says it's done or
+/// the timeout passes.
+///
+/// Cleanup acknowledgements carry the command and the group, so those left over from another
+/// scene aren't counted.
+fn await_cleanup(
+    port: &mut dyn ModemTransport,
+    group: Group,
+    command: Command,
+    timeout: Duration,
+) -> Result<SceneReport> {
+    let deadline = Instant::now() + timeout;
+    let [cmd1, _]: [u8; 2] = command.into();
+    let mut report = SceneReport::default();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match serial::next_message_timeout(port, remaining) {
+            Ok(Message::Received(msg)) => {
+                let acked = msg.flags.message_type() == MessageType::AllLinkCleanupAck
+                    && msg.cmd1 == cmd1
+                    && msg.cmd2 == group;
+                if acked && !report.succeeded.contains(&msg.from) {
+                    report.succeeded.push(msg.from);
+                }
+            }
+            Ok(Message::LinkCleanupFailed(failed, address)) if failed == group => {
+                report.failed.push(address);
+            }
+            Ok(Message::LinkCleanupStatus(completed)) => {
+                report.completed = completed;
+                return Ok(report);
+            }
+            Ok(_) => {}
+            Err(SerialError::Timeout { partial: false }) => return Ok(report),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Waits for the given device's SET button pressed broadcast, which follows its ACK of an ID
+/// request.
+fn await_set_button_broadcast(
+    port: &mut dyn ModemTransport,
+    address: Address,
+    timeout: Duration,
+) -> Result<(Category, u8, u8)> {
+    wait_for_map(port, timeout, |msg| match msg {
+        Message::Received(msg) if msg.from == address => msg.as_device_broadcast(),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::ReceivedKind;
+    use crate::mock::MockPort;
+
+    #[test]
+    fn send_and_await_ack() {
+        let input = [
+            // The modem's echo of the command, and its ACK.
+            0x02, 0x62, 0x11, 0x22, 0x33, 0x0F, 0x13, 0x00, 0x06,
+            // An unrelated broadcast.
+            0x02, 0x50, 0x44, 0x55, 0x66, 0x00, 0x00, 0x01, 0xCF, 0x11, 0x00,
+            // The device's ACK.
+            0x02, 0x50, 0x11, 0x22, 0x33, 0xAA, 0xBB, 0xCC, 0x2F, 0x13, 0x00,
+        ];
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        let address = Address::from([0x11, 0x22, 0x33]);
+        let ack = modem
+            .send_and_await_ack(address, Command::Off(None), DEFAULT_TIMEOUT)
+            .unwrap();
+        assert_eq!(ack.received_kind(), Some(ReceivedKind::DirectAck));
+        assert_eq!(ack.sender(), Some(address));
+    }
+
+    #[test]
+    fn ping() {
+        let input = [
+            0x02, 0x62, 0x11, 0x22, 0x33, 0x0F, 0x0F, 0x00, 0x06, 0x02, 0x50, 0x11, 0x22, 0x33,
+            0xAA, 0xBB, 0xCC, 0x2F, 0x0F, 0x00,
+        ];
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        let address = Address::from([0x11, 0x22, 0x33]);
+        assert!(modem.ping(address, DEFAULT_TIMEOUT).unwrap() < DEFAULT_TIMEOUT);
+        // The modem sends the ping, but the device never answers.
+        let mut modem = Modem::new(Box::new(MockPort::new(&input[..9])));
+        match modem.ping(address, Duration::from_millis(10)) {
+            Err(SerialError::Timeout { .. }) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn get_status() {
+        let input = [
+            0x02, 0x62, 0x11, 0x22, 0x33, 0x0F, 0x19, 0x00, 0x06,
+            // The ACK carries the database delta (0x05) and the on level (0x7F).
+            0x02, 0x50, 0x11, 0x22, 0x33, 0xAA, 0xBB, 0xCC, 0x2F, 0x05, 0x7F,
+        ];
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        let status = modem.get_status(Address::from([0x11, 0x22, 0x33]));
+        assert_eq!(status.unwrap(), 0x7F);
+    }
+
+    #[test]
+    fn identify() {
+        let input = [
+            0x02, 0x62, 0x11, 0x22, 0x33, 0x0F, 0x10, 0x00, 0x06, 0x02, 0x50, 0x11, 0x22, 0x33,
+            0xAA, 0xBB, 0xCC, 0x2F, 0x10, 0x00,
+            // The SET button pressed broadcast: a dimmer (01.20) running firmware 0x41.
+            0x02, 0x50, 0x11, 0x22, 0x33, 0x01, 0x20, 0x41, 0x8F, 0x01, 0x00,
+            // The engine version request.
+            0x02, 0x62, 0x11, 0x22, 0x33, 0x0F, 0x0D, 0x00, 0x06, 0x02, 0x50, 0x11, 0x22, 0x33,
+            0xAA, 0xBB, 0xCC, 0x2F, 0x0D, 0x02,
+        ];
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        let info = modem.identify(Address::from([0x11, 0x22, 0x33]), DEFAULT_TIMEOUT);
+        let info = info.unwrap();
+        assert_eq!(info.category, [0x01, 0x20].into());
+        assert_eq!(info.firmware, Some(Firmware(0x41)));
+        assert_eq!(info.engine, EngineVersion::I2cs);
+    }
+
+    #[test]
+    fn send_x10() {
+        // A3 (0x62 0x00), then A On (0x62 0x80), each echoed and ACKed.
+        let input = [0x02, 0x63, 0x62, 0x00, 0x06, 0x02, 0x63, 0x62, 0x80, 0x06];
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        let unit = UnitCode::new(3).unwrap();
+        modem
+            .send_x10(HouseCode::A, unit, x10::Command::On)
+            .unwrap();
+    }
+
+    #[test]
+    fn link_count_is_cached() {
+        let config = [0x02, 0x73, 0x00, 0x07, 0x00, 0x06];
+        let mut input = config.to_vec();
+        // One record, then a NAK for the next.
+        input.extend_from_slice(&[0x02, 0x69, 0x06]);
+        input.extend_from_slice(&[0x02, 0x57, 0xE2, 0x01, 0x11, 0x22, 0x33, 0x01, 0x20, 0x41]);
+        input.extend_from_slice(&[0x02, 0x6A, 0x15]);
+        // The delta hasn't changed the second time around.
+        input.extend_from_slice(&config);
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        assert_eq!(modem.link_count().unwrap(), 1);
+        assert_eq!(modem.link_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn link_count_is_recounted() {
+        let record = [0x02, 0x57, 0xE2, 0x01, 0x11, 0x22, 0x33, 0x01, 0x20, 0x41];
+        let count = |records: usize| {
+            let mut input = vec![0x02, 0x69, 0x06];
+            for _ in 0..records {
+                input.extend_from_slice(&record);
+                input.extend_from_slice(&[0x02, 0x6A, 0x06]);
+            }
+            input.truncate(input.len() - 1);
+            input.push(0x15);
+            input
+        };
+        for &delta in &[0x07, 0x00] {
+            let config = [0x02, 0x73, 0x00, delta, 0x00, 0x06];
+            let mut input = [&config[..], &count(1)].concat();
+            if delta != 0 {
+                // A device links, and the delta stays the same.
+                input.extend_from_slice(&[0x02, 0x64, 0x01, 0x05, 0x06]);
+                input.extend_from_slice(&[0x02, 0x53, 0x01, 0x05, 0x44, 0x55, 0x66, 0x02, 0x1A]);
+                input.push(0x41);
+            }
+            input.extend_from_slice(&[&config[..], &count(2)].concat());
+            let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+            assert_eq!(modem.link_count().unwrap(), 1);
+            if delta != 0 {
+                let timeout = Duration::from_millis(10);
+                modem.link_device(LinkRole::Controller, 5, timeout).unwrap();
+            }
+            assert_eq!(modem.link_count().unwrap(), 2, "Delta {:#04x}", delta);
+        }
+    }
+
+    #[test]
+    fn scene_on() {
+        let input = [
+            0x02, 0x61, 0x01, 0x11, 0x01, 0x06,
+            // 11.22.33 acknowledges the cleanup, 44.55.66 doesn't.
+            0x02, 0x50, 0x11, 0x22, 0x33, 0xAA, 0xBB, 0xCC, 0x6F, 0x11, 0x01, 0x02, 0x56, 0x01,
+            0x01, 0x44, 0x55, 0x66, 0x02, 0x58, 0x06,
+        ];
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        let report = modem.scene_on(0x01).unwrap();
+        assert_eq!(report.succeeded, [Address::from([0x11, 0x22, 0x33])]);
+        assert_eq!(report.failed, [Address::from([0x44, 0x55, 0x66])]);
+        assert!(report.completed);
+    }
+
+    #[test]
+    fn scene_ignores_stale_cleanup_acks() {
+        let input = [
+            0x02, 0x61, 0x01, 0x11, 0x01, 0x06,
+            // A cleanup acknowledgement for group 2, and one for turning group 1 off.
+            0x02, 0x50, 0x11, 0x22, 0x33, 0xAA, 0xBB, 0xCC, 0x6F, 0x11, 0x02, 0x02, 0x50, 0x44,
+            0x55, 0x66, 0xAA, 0xBB, 0xCC, 0x6F, 0x13, 0x01, 0x02, 0x58, 0x06,
+        ];
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        let report = modem.scene_on(0x01).unwrap();
+        assert!(report.succeeded.is_empty());
+        assert!(report.completed);
+    }
+
+    #[test]
+    fn wait_for() {
+        let mut input = vec![0x02, 0x54, 0x02];
+        input.extend_from_slice(&[
+            0x02, 0x50, 0x11, 0x22, 0x33, 0x00, 0x00, 0x01, 0xCF, 0x11, 0x00,
+        ]);
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        let timeout = Duration::from_millis(10);
+        let msg = modem
+            .wait_for(|msg| msg.sender().is_some(), timeout)
+            .unwrap();
+        assert_eq!(msg.sender(), Some(Address::from([0x11, 0x22, 0x33])));
+        match modem.wait_for(|_| true, timeout) {
+            Err(SerialError::Timeout { partial: false }) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn address_is_cached() {
+        let input = [0x02, 0x60, 0x11, 0x22, 0x33, 0x03, 0x15, 0x9B, 0x06];
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        let address = Address::from([0x11, 0x22, 0x33]);
+        assert_eq!(modem.address().unwrap(), address);
+        // The modem is only asked once (there's no second answer to read).
+        assert!(modem.is_self(address).unwrap());
+        assert!(!modem.is_self(Address::BROADCAST).unwrap());
+    }
+
+    #[test]
+    fn link_device() {
+        let address = Address::from([0x11, 0x22, 0x33]);
+        let mut input = vec![0x02, 0x64, 0x01, 0x05, 0x06];
+        input.extend_from_slice(&[0x02, 0x53, 0x01, 0x05, 0x11, 0x22, 0x33, 0x02, 0x1A, 0x41]);
+        let mut modem = Modem::new(Box::new(MockPort::new(&input)));
+        let timeout = Duration::from_millis(10);
+        let result = modem.link_device(LinkRole::Controller, 5, timeout).unwrap();
+        assert_eq!(result.id(), address);
+        // Nothing links the second time, so linking is canceled.
+        let mut port = MockPort::new(&[0x02, 0x64, 0x01, 0x05, 0x06]);
+        port.reply_to(&[0x02, 0x65], &[0x02, 0x65, 0x06]);
+        let mut modem = Modem::new(Box::new(port));
+        match modem.link_device(LinkRole::Controller, 5, timeout) {
+            Err(SerialError::LinkTimeout) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+}
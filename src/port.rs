@@ -1,14 +1,167 @@
 //! Serial port utilities.
 use std::ffi::OsStr;
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+use std::{error, fmt};
 
 use serialport::prelude::*;
 
+use crate::transport::ModemTransport;
+
+/// The reasons a serial port can fail to open.
+#[derive(Debug)]
+pub enum PortError {
+    /// There's no port by that name.
+    NotFound(serialport::Error),
+    /// The port exists, but the user isn't allowed to open it (on Linux, this usually means the
+    /// user isn't in the `dialout` group).
+    PermissionDenied(serialport::Error),
+    /// The port is already open in another program.
+    InUse(serialport::Error),
+    /// The port couldn't be opened for some other reason.
+    Other(serialport::Error),
+}
+
+impl PortError {
+    /// The underlying error.
+    pub fn inner(&self) -> &serialport::Error {
+        match self {
+            PortError::NotFound(e)
+            | PortError::PermissionDenied(e)
+            | PortError::InUse(e)
+            | PortError::Other(e) => e,
+        }
+    }
+}
+
+/// Classifies the error by its kind, falling back on its description.
+///
+/// On Unix-like systems, `serialport` reports most failures to open a port as unknown errors, so
+/// the only clue is the system's description of the error. On Windows, a port that's in use can't
+/// be told apart from one that doesn't exist; both are reported as `NotFound`.
+impl From<serialport::Error> for PortError {
+    fn from(error: serialport::Error) -> Self {
+        let description = error.description.to_lowercase();
+        match error.kind() {
+            serialport::ErrorKind::NoDevice
+            | serialport::ErrorKind::Io(io::ErrorKind::NotFound) => PortError::NotFound(error),
+            serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied) => {
+                PortError::PermissionDenied(error)
+            }
+            _ if description.contains("no such file") => PortError::NotFound(error),
+            _ if description.contains("permission denied") => PortError::PermissionDenied(error),
+            _ if description.contains("busy") => PortError::InUse(error),
+            _ => PortError::Other(error),
+        }
+    }
+}
+
+impl fmt::Display for PortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortError::NotFound(_) => write!(f, "The serial port doesn't exist"),
+            PortError::PermissionDenied(_) => {
+                write!(f, "Permission to open the serial port was denied")
+            }
+            PortError::InUse(_) => write!(f, "The serial port is in use by another program"),
+            PortError::Other(e) => write!(f, "The serial port couldn't be opened: {}", e),
+        }
+    }
+}
+
+impl error::Error for PortError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(self.inner())
+    }
+}
+
 /// Opens the named serial port with appropriate settings.
-pub fn open<S: AsRef<OsStr>>(
-    name: S,
-) -> Result<Box<dyn serialport::SerialPort>, serialport::Error> {
-    let mut settings = SerialPortSettings::default();
-    settings.baud_rate = 19_200;
-    settings.timeout = std::time::Duration::from_millis(500);
-    serialport::open_with_settings(&name, &settings)
+pub fn open<S: AsRef<OsStr>>(name: S) -> Result<Box<dyn serialport::SerialPort>, PortError> {
+    let settings = SerialPortSettings {
+        baud_rate: 19_200,
+        timeout: std::time::Duration::from_millis(500),
+        ..Default::default()
+    };
+    Ok(serialport::open_with_settings(&name, &settings)?)
+}
+
+/// Connects to a modem exposed over TCP (by a serial bridge such as `ser2net` or `socat`), giving
+/// up on connecting after the given timeout.
+///
+/// The bridge must pass bytes through untouched (a raw TCP port, not telnet), and the serial side
+/// must already be set up as `open` would (19,200 baud, 8N1).
+pub fn open_tcp(address: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&address, timeout)?;
+    // Frames are short and each one is waited on, so don't hold them back to coalesce writes.
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Reads from the stream without waiting, by briefly making it nonblocking.
+///
+/// The bridge closing the connection is reported as an error (rather than as nothing to read),
+/// so that readers don't wait on it forever.
+impl ModemTransport for TcpStream {
+    fn bytes_to_read(&mut self) -> io::Result<usize> {
+        let mut buf = [0; 64];
+        self.set_nonblocking(true)?;
+        let peeked = self.peek(&mut buf);
+        self.set_nonblocking(false)?;
+        match peeked {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+    fn clear_input(&mut self) -> io::Result<()> {
+        let mut buf = [0; 64];
+        loop {
+            match self.bytes_to_read()? {
+                0 => return Ok(()),
+                n => self.read_exact(&mut buf[..n])?,
+            }
+        }
+    }
+    fn try_clone(&self) -> io::Result<Box<dyn ModemTransport>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::serial;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn open_missing_port() {
+        match open("/dev/this-port-does-not-exist") {
+            Err(PortError::NotFound(_)) => {}
+            result => panic!("Unexpected result {:?}", result.map(|_| ())),
+        }
+        let busy =
+            serialport::Error::new(serialport::ErrorKind::Unknown, "Device or resource busy");
+        assert!(matches!(PortError::from(busy), PortError::InUse(_)));
+    }
+
+    #[test]
+    fn tcp_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let bridge = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(&[0x02, 0x55]).unwrap();
+        });
+        let mut stream = open_tcp(address, Duration::from_secs(1)).unwrap();
+        let timeout = Duration::from_secs(1);
+        let msg = serial::next_message_timeout(&mut stream, timeout).unwrap();
+        assert_eq!(msg, Message::UserResetDetected);
+        bridge.join().unwrap();
+        assert!(serial::next_message_timeout(&mut stream, timeout).is_err());
+    }
 }
@@ -1,209 +1,2153 @@
 //! Utilities for communicating with a modem over a serial port.
+//!
+//! Functions that send a command wait for the modem's response for up to the given timeout, after
+//! which they give up with `SerialError::Timeout`. Unrelated frames that arrive in the meantime
+//! don't extend the wait.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{error, fmt, io};
 
 use crate::command::Command;
+use crate::device::{Address, Category, EngineVersion, Firmware};
 use crate::message::*;
-use serialport::SerialPort;
+use crate::transport::ModemTransport;
+use crate::x10::{HouseCode, Payload};
+
+/// The acknowledgement byte sent by the modem when a command succeeds.
+const ACK: u8 = 0x06;
+
+/// The negative acknowledgement byte sent by the modem when a command fails.
+const NAK: u8 = 0x15;
+
+/// How long to wait before checking the port again when no bytes have arrived.
+///
+/// At 19,200 baud a byte takes about half a millisecond to arrive, so this adds little latency
+/// while keeping an idle reader from spinning.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How long the line must be quiet for it to be considered between frames (by `sync`, and by
+/// `drain_messages` when a frame stops short).
+///
+/// This is many times the gap between the bytes of a frame (about half a millisecond at 19,200
+/// baud).
+const SYNC_GAP: Duration = Duration::from_millis(20);
+
+/// An error encountered while communicating with the modem.
+#[derive(Debug)]
+pub enum SerialError {
+    /// The underlying port reported an I/O error.
+    Io(io::Error),
+    /// The modem refused the command (NAK).
+    ///
+    /// The modem echoes a command it refuses (when it's busy, say) before the NAK; see `Rejected`
+    /// for commands it couldn't make sense of.
+    Nak,
+    /// The modem rejected the command with the given opcode outright, answering with a NAK and no
+    /// echo.
+    ///
+    /// This usually means the frame was malformed (e.g. too short for its opcode).
+    Rejected(u8),
+    /// The modem replied with a response other than the one expected for the sent command.
+    UnexpectedResponse(Response),
+    /// The expected message or response didn't arrive in time.
+    ///
+    /// If the timeout struck partway through a frame (`partial`), the rest of the frame may still
+    /// arrive; discard it (see `clear_input`) before reading again.
+    Timeout { partial: bool },
+    /// An extended message arrived with the wrong checksum (see `ChecksumMode`).
+    BadChecksum(Received),
+    /// The bytes being parsed ended before a complete frame (see `Message::parse`).
+    Incomplete,
+    /// No link completed in time (see `Modem::link_device`), and linking was canceled.
+    LinkTimeout,
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SerialError::Io(e) => write!(f, "I/O error: {}", e),
+            SerialError::Nak => write!(f, "The modem refused the command (NAK)"),
+            SerialError::Rejected(opcode) => {
+                write!(f, "The modem rejected command {:#04x} as malformed", opcode)
+            }
+            SerialError::UnexpectedResponse(response) => {
+                write!(f, "Unexpected response from the modem: {:?}", response)
+            }
+            SerialError::Timeout { partial: false } => write!(f, "Timed out waiting for the modem"),
+            SerialError::Timeout { partial: true } => {
+                write!(f, "Timed out partway through a frame from the modem")
+            }
+            SerialError::Incomplete => write!(f, "The bytes ended partway through a frame"),
+            SerialError::LinkTimeout => write!(f, "No device linked before linking timed out"),
+            SerialError::BadChecksum(msg) => {
+                write!(f, "Bad checksum on extended message from {}", msg.from)
+            }
+        }
+    }
+}
+
+impl error::Error for SerialError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SerialError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SerialError {
+    fn from(e: io::Error) -> Self {
+        SerialError::Io(e)
+    }
+}
+
+/// The result of a modem operation.
+pub type Result<T> = std::result::Result<T, SerialError>;
+
+/// Whether to check the checksums of received extended messages.
+///
+/// Only I2CS devices fill in the checksum, so checking every message rejects valid ones from older
+/// devices.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ChecksumMode {
+    /// Don't check checksums (the default).
+    #[default]
+    Off,
+    /// Check the checksum of every extended message.
+    Verify,
+    /// Check the checksums of extended messages from devices known to run the I2CS engine.
+    VerifyKnownEngines,
+}
+
+impl ChecksumMode {
+    /// Checks the given message's checksum (if it has one and this mode calls for it), given the
+    /// engine version of the device it came from (if known).
+    pub fn check(self, msg: &Received, engine: Option<EngineVersion>) -> Result<()> {
+        let verify = match self {
+            ChecksumMode::Off => false,
+            ChecksumMode::Verify => true,
+            ChecksumMode::VerifyKnownEngines => {
+                engine.is_some_and(EngineVersion::requires_checksum)
+            }
+        };
+        match msg.has_valid_checksum() {
+            Some(false) if verify => Err(SerialError::BadChecksum(*msg)),
+            _ => Ok(()),
+        }
+    }
+}
 
 /// Gets the next byte from the given port.
-pub fn next_byte(port: &mut dyn SerialPort) -> Option<u8> {
-    while let Ok(n) = port.bytes_to_read() {
-        if n == 0 {
+pub fn next_byte(port: &mut dyn ModemTransport) -> Result<u8> {
+    read_byte(port, None)
+}
+
+/// Writes out anything the port has buffered but not yet sent.
+pub fn flush(port: &mut dyn ModemTransport) -> Result<()> {
+    Ok(port.flush()?)
+}
+
+/// Discards everything waiting to be read from the port.
+///
+/// This is useful after a timeout partway through a frame, to drop the rest of the frame (should
+/// it arrive late) before reading again.
+pub fn clear_input(port: &mut dyn ModemTransport) -> Result<()> {
+    Ok(port.clear_input()?)
+}
+
+/// Brings the port into alignment with the frames arriving on it, giving up after the given
+/// timeout.
+///
+/// Anything already waiting is discarded (it may start partway through a frame), and then so is
+/// everything that arrives until the line has been quiet for a while (20 ms). The modem sends each
+/// frame in one go, so the next byte after a quiet spell starts a frame. Scanning for a start byte
+/// followed by a known opcode instead wouldn't be enough, since that pair can appear inside a
+/// frame (as part of an address, say).
+///
+/// If the line is never quiet for long enough, `SerialError::Timeout` is returned.
+pub fn sync(port: &mut dyn ModemTransport, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    clear_input(port)?;
+    let mut quiet_since = Instant::now();
+    loop {
+        let available = port.bytes_to_read()?;
+        let now = Instant::now();
+        if available > 0 {
+            let mut buf = vec![0; available];
+            port.read_exact(&mut buf)?;
+            debug!("Discarded {:02X?} while syncing", buf);
+            quiet_since = now;
+        } else if now.duration_since(quiet_since) >= SYNC_GAP {
+            return Ok(());
+        } else if now >= deadline {
+            return Err(SerialError::Timeout { partial: false });
+        } else {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Somewhere the decoders can read bytes from.
+trait Source {
+    /// Returns the number of bytes that can be read without waiting.
+    fn available(&mut self) -> Result<usize>;
+    /// Fills the buffer, which must be no longer than the number of bytes available.
+    fn read_available(&mut self, buf: &mut [u8]) -> Result<()>;
+    /// Called once the start byte and (known) opcode of a frame have been read; returns whether
+    /// the frame should be abandoned because it's been cut short by another frame.
+    ///
+    /// If the frame is abandoned, the next byte read is the start byte of the frame that cut it
+    /// short.
+    fn abandon_frame(&mut self, _opcode: u8, _deadline: Option<Instant>) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+impl<'a> Source for dyn ModemTransport + 'a {
+    fn available(&mut self) -> Result<usize> {
+        Ok(self.bytes_to_read()?)
+    }
+    fn read_available(&mut self, buf: &mut [u8]) -> Result<()> {
+        Ok(self.read_exact(buf)?)
+    }
+}
+
+/// Bytes that have already been captured, for decoding without a port.
+struct Slice<'a> {
+    bytes: &'a [u8],
+    consumed: usize,
+}
+
+impl<'a> Source for Slice<'a> {
+    /// Running out of bytes is an error, since no more are coming.
+    fn available(&mut self) -> Result<usize> {
+        match self.bytes.len() - self.consumed {
+            0 => Err(SerialError::Incomplete),
+            available => Ok(available),
+        }
+    }
+    fn read_available(&mut self, buf: &mut [u8]) -> Result<()> {
+        let end = self.consumed + buf.len();
+        buf.copy_from_slice(&self.bytes[self.consumed..end]);
+        self.consumed = end;
+        Ok(())
+    }
+}
+
+impl Message {
+    /// Decodes the first message in the given bytes, returning it along with the number of bytes
+    /// it took up (including anything skipped before it).
+    ///
+    /// This decodes captured traffic (a log, say) the same way messages are read from a port.
+    /// Responses and unknown frames before the message are skipped; if the bytes end before a
+    /// complete message, `SerialError::Incomplete` is returned.
+    pub fn parse(bytes: &[u8]) -> Result<(Message, usize)> {
+        let mut slice = Slice { bytes, consumed: 0 };
+        let msg = message_before(&mut slice, &mut |_, _| {}, None)?;
+        Ok((msg, slice.consumed))
+    }
+}
+
+impl Response {
+    /// Decodes the first response in the given bytes, returning it along with the number of bytes
+    /// it took up (including anything skipped before it).
+    ///
+    /// As with `Message::parse`, messages and unknown frames before the response are skipped.
+    /// The ACK or NAK that follows the response isn't consumed, except for X10 responses (which
+    /// hold it as their status).
+    pub fn parse(bytes: &[u8]) -> Result<(Response, usize)> {
+        let mut slice = Slice { bytes, consumed: 0 };
+        let response = decode_response(&mut slice, &mut |_, _| {}, None, None)?;
+        Ok((response, slice.consumed))
+    }
+}
+
+/// Returns the number of bytes waiting to be read from the port.
+fn available<S: Source + ?Sized>(port: &mut S) -> Result<usize> {
+    port.available()
+}
+
+/// Reads a single byte from the port, giving up at the deadline (if any).
+fn read_byte<S: Source + ?Sized>(port: &mut S, deadline: Option<Instant>) -> Result<u8> {
+    let mut buf = [0];
+    read_bytes(port, &mut buf, deadline)?;
+    Ok(buf[0])
+}
+
+/// Fills the buffer with bytes from the port, giving up at the deadline (if any).
+fn read_bytes<S: Source + ?Sized>(
+    port: &mut S,
+    buf: &mut [u8],
+    deadline: Option<Instant>,
+) -> Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let available = available(port)?;
+        if available == 0 {
+            let wait = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if remaining > Duration::from_secs(0) => {
+                        remaining.min(POLL_INTERVAL)
+                    }
+                    _ => return Err(SerialError::Timeout { partial: false }),
+                },
+                None => POLL_INTERVAL,
+            };
+            thread::sleep(wait);
             continue;
         }
-        let mut buf = [0];
-        return port.read_exact(&mut buf).ok().map(|_| buf[0]);
+        let end = buf.len().min(filled + available);
+        port.read_available(&mut buf[filled..end])?;
+        trace!("Read {:02X?}", &buf[filled..end]);
+        filled = end;
     }
-    None
+    Ok(())
+}
+
+/// Returns the length of the frame at the start of the given bytes (including the start byte
+/// and opcode), or `None` if the opcode is unknown or more bytes are needed to tell.
+fn frame_length(frame: &[u8]) -> Option<usize> {
+    use self::Opcode::*;
+    let body = match Opcode::from(*frame.get(1)?) {
+        StandardMessageReceived => 9,
+        ExtendedMessageReceived => 23,
+        X10Received => 2,
+        ButtonEvent | LinkCleanupStatus => 1,
+        UserReset => 0,
+        LinkCleanupFailed => 5,
+        LinkRecordResponse | LinkComplete => 8,
+        DatabaseRecordFound => 10,
+        GetInfo => 6,
+        SendLinkCommand | SetCategory | GetConfig => 3,
+        SendMessage => {
+            let flags = MessageFlags(*frame.get(5)?);
+            if flags.is_extended() {
+                20
+            } else {
+                6
+            }
+        }
+        SendX10 | StartLinking | SetAckBytes | Sleep | ReadDatabase => 2,
+        SetAckByte | SetConfig | SetNakByte | SetStatus => 1,
+        SetLinkData => 3,
+        SetRetries | SetFrequencyOffset | SetTempLincAck => 1,
+        CancelLinking | Reset | GetFirstLinkRecord | GetNextLinkRecord | GetSenderLinkRecord
+        | LedOn | LedOff | CancelCleanup | Beep => 0,
+        ManageLinkRecord => 9,
+        Unknown(_) => return None,
+    };
+    Some(body + 2)
+}
+
+/// Whether the frame of the given length at the start of the given bytes is followed directly by
+/// the start of another frame (or by nothing at all).
+///
+/// Responses may be followed by the acknowledgement byte first.
+fn is_aligned(frame: &[u8], length: usize) -> bool {
+    let mut rest = &frame[length..];
+    if frame[1] >= 0x60 && matches!(rest.first(), Some(0x06) | Some(0x15)) {
+        rest = &rest[1..];
+    }
+    rest.first().is_none_or(|&byte| byte == frame::START)
 }
 
 /// Reads the next full message from the given port.
-pub fn next_message(port: &mut dyn SerialPort) -> Option<Message> {
+pub fn next_message(port: &mut dyn ModemTransport) -> Result<Message> {
+    message_before(port, &mut |_, _| {}, None)
+}
+
+/// Reads the next full message from the given port, giving up after the given timeout.
+pub fn next_message_timeout(port: &mut dyn ModemTransport, timeout: Duration) -> Result<Message> {
+    message_before(port, &mut |_, _| {}, Some(Instant::now() + timeout))
+}
+
+/// Reads every message that has already arrived on the given port.
+///
+/// Messages are read until the port has no more unread bytes between frames. A frame that has
+/// only partially arrived is given until the line has been quiet for a moment to finish; if it
+/// doesn't, it's discarded and the messages read before it are returned.
+pub fn drain_messages(port: &mut dyn ModemTransport) -> Result<Vec<Message>> {
+    let mut messages = Vec::new();
+    loop {
+        let deadline = Instant::now() + SYNC_GAP;
+        match decode_message(port, &mut |_, _| {}, Some(deadline), false) {
+            Ok(Some(msg)) => messages.push(msg),
+            Ok(None) | Err(SerialError::Timeout { .. }) => return Ok(messages),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Reads the next button event from the given port. Other messages (and responses) are ignored.
+pub fn next_button_event(port: &mut dyn ModemTransport) -> Result<ButtonEvent> {
+    loop {
+        if let Message::ButtonEvent(event) = next_message(port)? {
+            return Ok(event);
+        }
+    }
+}
+
+/// Reads the next full message from the given port, reporting unknown opcodes to `on_unknown`
+/// and giving up at the deadline (if any).
+fn message_before<S: Source + ?Sized>(
+    port: &mut S,
+    on_unknown: &mut dyn FnMut(u8, usize),
+    deadline: Option<Instant>,
+) -> Result<Message> {
+    loop {
+        if let Some(msg) = decode_message(port, on_unknown, deadline, true)? {
+            return Ok(msg);
+        }
+    }
+}
+
+/// Reads the next full message from the given port, reporting unknown opcodes to `on_unknown`
+/// and giving up at the deadline (if any).
+///
+/// If `wait` is false, `None` is returned as soon as there are no more bytes to read between
+/// frames.
+fn decode_message<S: Source + ?Sized>(
+    port: &mut S,
+    on_unknown: &mut dyn FnMut(u8, usize),
+    deadline: Option<Instant>,
+    wait: bool,
+) -> Result<Option<Message>> {
+    let mut in_frame = false;
+    let msg = read_message(port, on_unknown, deadline, wait, &mut in_frame);
+    mark_partial(msg, in_frame)
+}
+
+/// Marks a timeout as partial if it struck partway through a frame.
+fn mark_partial<T>(result: Result<T>, in_frame: bool) -> Result<T> {
+    match result {
+        Err(SerialError::Timeout { .. }) => Err(SerialError::Timeout { partial: in_frame }),
+        result => result,
+    }
+}
+
+/// Does the work of `decode_message`, keeping track of whether it's partway through a frame.
+fn read_message<S: Source + ?Sized>(
+    port: &mut S,
+    on_unknown: &mut dyn FnMut(u8, usize),
+    deadline: Option<Instant>,
+    wait: bool,
+    in_frame: &mut bool,
+) -> Result<Option<Message>> {
     use self::Message::*;
-    Some(loop {
+    let mut unknown = None;
+    let mut skipped = 0;
+    let msg = loop {
+        *in_frame = false;
+        if !wait && available(port)? == 0 {
+            return Ok(None);
+        }
         // All messages start with 0x02, so if we're not seeing that, we're in the middle of a
         // message and should wait it out.
-        if next_byte(port).unwrap() == 0x02 {
-            break match next_byte(port).unwrap() {
-                0x50 => {
-                    let mut buf = [0; 6];
-                    port.read_exact(&mut buf).unwrap();
-                    Received(
-                        [buf[0], buf[1], buf[2]].into(),
-                        Command::try_from([buf[3], buf[4]]),
-                        buf[5],
-                        None,
-                    )
-                }
-                0x51 => {
-                    let mut buf = [0; 20];
-                    port.read_exact(&mut buf).unwrap();
-                    let data = [
-                        buf[6], buf[7], buf[8], buf[9], buf[10], buf[11], buf[12], buf[13],
-                        buf[14], buf[15], buf[16], buf[17], buf[18], buf[19],
-                    ];
-                    Received(
-                        [buf[0], buf[1], buf[2]].into(),
-                        Command::try_from([buf[3], buf[4]]),
-                        buf[5],
-                        Some(data),
-                    )
-                }
-                0x52 => {
-                    let mut buf = [0; 3];
-                    port.read_exact(&mut buf).unwrap();
-                    if let Some(msg) = crate::x10::Message::try_from(buf) {
+        if read_byte(port, deadline)? == frame::START {
+            *in_frame = true;
+            if let Some(opcode) = unknown.take() {
+                debug!(
+                    "Skipped unknown opcode {:#04x} and {} bytes after it",
+                    opcode, skipped
+                );
+                on_unknown(opcode, skipped);
+                skipped = 0;
+            }
+            let opcode = read_byte(port, deadline)?;
+            if Opcode::from(opcode).is_known() && port.abandon_frame(opcode, deadline)? {
+                continue;
+            }
+            break match Opcode::from(opcode) {
+                Opcode::StandardMessageReceived => {
+                    let mut buf = [0; 9];
+                    read_bytes(port, &mut buf, deadline)?;
+                    Received(self::Received {
+                        from: [buf[0], buf[1], buf[2]].into(),
+                        to: [buf[3], buf[4], buf[5]].into(),
+                        flags: buf[6].into(),
+                        cmd1: buf[7],
+                        cmd2: buf[8],
+                        data: None,
+                    })
+                }
+                Opcode::ExtendedMessageReceived => {
+                    let mut buf = [0; 23];
+                    read_bytes(port, &mut buf, deadline)?;
+                    let mut data = [0; 14];
+                    data.copy_from_slice(&buf[9..]);
+                    Received(self::Received {
+                        from: [buf[0], buf[1], buf[2]].into(),
+                        to: [buf[3], buf[4], buf[5]].into(),
+                        flags: buf[6].into(),
+                        cmd1: buf[7],
+                        cmd2: buf[8],
+                        data: Some(data),
+                    })
+                }
+                Opcode::X10Received => {
+                    let mut buf = [0; 2];
+                    read_bytes(port, &mut buf, deadline)?;
+                    // Received X10 messages carry no status byte.
+                    if let Ok(msg) = <crate::x10::Message as TryFrom<[u8; 2]>>::try_from(buf) {
                         X10Received(msg)
                     } else {
                         continue;
                     }
                 }
-                0x53 => {
+                Opcode::LinkComplete => {
                     let mut buf = [0; 8];
-                    port.read_exact(&mut buf).unwrap();
+                    read_bytes(port, &mut buf, deadline)?;
                     LinkComplete(buf.into())
                 }
-                0x54 => match next_byte(port).unwrap() {
-                    0x02 => ButtonEvent(self::ButtonEvent::Tapped(self::Button::Set)),
-                    0x03 => ButtonEvent(self::ButtonEvent::Held(self::Button::Set)),
-                    0x04 => ButtonEvent(self::ButtonEvent::Released(self::Button::Set)),
-                    0x12 => ButtonEvent(self::ButtonEvent::Tapped(self::Button::Two)),
-                    0x13 => ButtonEvent(self::ButtonEvent::Held(self::Button::Two)),
-                    0x14 => ButtonEvent(self::ButtonEvent::Released(self::Button::Two)),
-                    0x22 => ButtonEvent(self::ButtonEvent::Tapped(self::Button::Three)),
-                    0x23 => ButtonEvent(self::ButtonEvent::Held(self::Button::Three)),
-                    0x24 => ButtonEvent(self::ButtonEvent::Released(self::Button::Three)),
-                    _ => {
-                        continue;
+                Opcode::ButtonEvent => {
+                    match self::ButtonEvent::from_im_code(read_byte(port, deadline)?) {
+                        Some(event) => ButtonEvent(event),
+                        None => continue,
                     }
-                },
-                0x55 => UserResetDetected,
-                0x56 => {
+                }
+                Opcode::UserReset => UserResetDetected,
+                Opcode::LinkCleanupFailed => {
                     // The next byte is always 0x01, so we don't need to worry about it.
-                    let _ = next_byte(port).unwrap();
+                    let _ = read_byte(port, deadline)?;
                     let mut buf = [0; 4];
-                    port.read_exact(&mut buf).unwrap();
+                    read_bytes(port, &mut buf, deadline)?;
                     LinkCleanupFailed(buf[0], [buf[1], buf[2], buf[3]].into())
                 }
-                0x57 => {
-                    let flags = next_byte(port).unwrap();
-                    let group = next_byte(port).unwrap();
+                Opcode::LinkRecordResponse => {
+                    let flags = read_byte(port, deadline)?;
+                    let group = read_byte(port, deadline)?;
                     let mut id = [0; 3];
-                    port.read_exact(&mut id).unwrap();
+                    read_bytes(port, &mut id, deadline)?;
                     let mut link = [0; 3];
-                    port.read_exact(&mut link).unwrap();
+                    read_bytes(port, &mut link, deadline)?;
                     LinkRecordResponse(flags, group, id.into(), link.into())
                 }
-                0x58 => LinkCleanupStatus(next_byte(port).unwrap() == 0x06),
-                0x59 => {
+                Opcode::LinkCleanupStatus => LinkCleanupStatus(read_byte(port, deadline)? == 0x06),
+                Opcode::DatabaseRecordFound => {
                     let mut address = [0; 2];
-                    port.read_exact(&mut address).unwrap();
-                    let flags = next_byte(port).unwrap();
-                    let group = next_byte(port).unwrap();
+                    read_bytes(port, &mut address, deadline)?;
+                    let flags = read_byte(port, deadline)?;
+                    let group = read_byte(port, deadline)?;
                     let mut id = [0; 3];
-                    port.read_exact(&mut id).unwrap();
+                    read_bytes(port, &mut id, deadline)?;
                     let mut link = [0; 3];
-                    port.read_exact(&mut link).unwrap();
-                    DatabaseRecordFound(address, flags, group, id.into(), link.into())
+                    read_bytes(port, &mut link, deadline)?;
+                    DatabaseRecordFound(DatabaseRecord {
+                        address,
+                        flags,
+                        group,
+                        id: id.into(),
+                        data: link.into(),
+                    })
                 }
-                _ => {
+                Opcode::Unknown(opcode) => {
+                    unknown = Some(opcode);
                     continue;
                 }
+                _ => continue,
             };
+        } else if unknown.is_some() {
+            skipped += 1;
         }
-    })
+    };
+    trace!("Decoded message {:?}", msg);
+    Ok(Some(msg))
 }
 
 /// Reads the next response from the given port. Non-response messages are ignored.
-pub fn next_response(port: &mut dyn SerialPort) -> Option<Response> {
+pub fn next_response(port: &mut dyn ModemTransport) -> Result<Response> {
+    decode_response(port, &mut |_, _| {}, None, None)
+}
+
+/// Reads the next response from the given port, giving up after the given timeout.
+/// Non-response messages are ignored.
+pub fn next_response_timeout(port: &mut dyn ModemTransport, timeout: Duration) -> Result<Response> {
+    decode_response(port, &mut |_, _| {}, Some(Instant::now() + timeout), None)
+}
+
+/// Reads the next response from the given port, reporting unknown opcodes to `on_unknown` and
+/// giving up at the deadline (if any).
+///
+/// If the opcode of a command that was just sent is given, a NAK that arrives between frames
+/// (instead of the command's echo) is reported as `SerialError::Rejected`.
+fn decode_response<S: Source + ?Sized>(
+    port: &mut S,
+    on_unknown: &mut dyn FnMut(u8, usize),
+    deadline: Option<Instant>,
+    sent: Option<u8>,
+) -> Result<Response> {
+    let mut in_frame = false;
+    let response = read_response(port, on_unknown, deadline, sent, &mut in_frame);
+    mark_partial(response, in_frame)
+}
+
+/// Does the work of `decode_response`, keeping track of whether it's partway through a frame.
+fn read_response<S: Source + ?Sized>(
+    port: &mut S,
+    on_unknown: &mut dyn FnMut(u8, usize),
+    deadline: Option<Instant>,
+    sent: Option<u8>,
+    in_frame: &mut bool,
+) -> Result<Response> {
     use self::Response::*;
-    Some(loop {
+    let mut unknown = None;
+    let mut skipped = 0;
+    let mut between_frames = true;
+    let response = loop {
+        *in_frame = false;
         // Wait for start byte
-        if next_byte(port).unwrap() == 0x02 {
-            break match next_byte(port).unwrap() {
-                0x60 => {
+        let byte = read_byte(port, deadline)?;
+        if let (true, NAK, Some(sent)) = (between_frames, byte, sent) {
+            return Err(SerialError::Rejected(sent));
+        }
+        between_frames = false;
+        if byte == frame::START {
+            *in_frame = true;
+            if let Some(opcode) = unknown.take() {
+                debug!(
+                    "Skipped unknown opcode {:#04x} and {} bytes after it",
+                    opcode, skipped
+                );
+                on_unknown(opcode, skipped);
+                skipped = 0;
+            }
+            let opcode = read_byte(port, deadline)?;
+            if Opcode::from(opcode).is_known() && port.abandon_frame(opcode, deadline)? {
+                continue;
+            }
+            break match Opcode::from(opcode) {
+                Opcode::GetInfo => {
                     let mut buf = [0; 6];
-                    port.read_exact(&mut buf).unwrap();
+                    read_bytes(port, &mut buf, deadline)?;
                     let address = [buf[0], buf[1], buf[2]].into();
-                    let category = [buf[3], buf[4]];
-                    let version = buf[5];
-                    let version = if version == 0xFF { None } else { Some(version) };
-                    GotInfo(address, category, version)
+                    let category = [buf[3], buf[4]].into();
+                    GotInfo(address, category, Firmware::from_raw(buf[5]))
                 }
-                0x61 => {
+                Opcode::SendLinkCommand => {
                     let mut buf = [0; 3];
-                    port.read_exact(&mut buf).unwrap();
+                    read_bytes(port, &mut buf, deadline)?;
                     let group = buf[0];
                     let command = buf[1];
                     let broadcast = buf[2];
                     SentLinkCommand(group, command, broadcast)
                 }
-                0x62 => unimplemented!(),
-                0x63 => {
+                Opcode::SendMessage => {
+                    let mut buf = [0; 6];
+                    read_bytes(port, &mut buf, deadline)?;
+                    let address = [buf[0], buf[1], buf[2]].into();
+                    let flags = MessageFlags(buf[3]);
+                    let command = [buf[4], buf[5]];
+                    let data = if flags.is_extended() {
+                        let mut data = [0; 14];
+                        read_bytes(port, &mut data, deadline)?;
+                        Some(data)
+                    } else {
+                        None
+                    };
+                    SentMessage(address, flags, command, data)
+                }
+                Opcode::SendX10 => {
+                    // Unlike other responses, the status byte is read along with the echo, since
+                    // it's part of the message.
                     let mut buf = [0; 3];
-                    port.read_exact(&mut buf).unwrap();
+                    read_bytes(port, &mut buf, deadline)?;
                     if let Some(msg) = crate::x10::Message::try_from(buf) {
                         SentX10(msg)
                     } else {
                         continue;
                     }
                 }
-                0x64 => {
+                Opcode::StartLinking => {
                     let mut buf = [0; 2];
-                    port.read_exact(&mut buf).unwrap();
-                    let role = buf[0];
+                    read_bytes(port, &mut buf, deadline)?;
+                    let role = buf[0].into();
                     let group = buf[1];
                     StartedLink(role, group)
                 }
-                0x65 => CanceledLink,
-                0x66 => {
+                Opcode::CancelLinking => CanceledLink,
+                Opcode::SetCategory => {
                     let mut buf = [0; 3];
-                    port.read_exact(&mut buf).unwrap();
-                    let category = [buf[0], buf[1]];
-                    let firmware = buf[2];
-                    let firmware = if firmware == 0x00 {
-                        None
-                    } else {
-                        Some(firmware)
-                    };
-                    SetCategory(category, firmware)
-                }
-                0x67 => Reset,
-                0x68 => SetAckByte(next_byte(port).unwrap()),
-                0x69 => GotFirstLinkRecord,
-                0x6A => GotNextLinkRecord,
-                0x6B => SetConfig(next_byte(port).unwrap().into()),
-                0x6C => GotSenderLinkRecord,
-                0x6D => LedOn,
-                0x6E => LedOff,
-                0x6F => {
+                    read_bytes(port, &mut buf, deadline)?;
+                    let category = [buf[0], buf[1]].into();
+                    SetCategory(category, Firmware::from_raw(buf[2]))
+                }
+                Opcode::Reset => Reset,
+                Opcode::SetAckByte => SetAckByte(read_byte(port, deadline)?),
+                Opcode::GetFirstLinkRecord => GotFirstLinkRecord,
+                Opcode::GetNextLinkRecord => GotNextLinkRecord,
+                Opcode::SetConfig => SetConfig(read_byte(port, deadline)?.into()),
+                Opcode::GetSenderLinkRecord => GotSenderLinkRecord,
+                Opcode::LedOn => LedOn,
+                Opcode::LedOff => LedOff,
+                Opcode::ManageLinkRecord => {
                     let mut buf = [0; 9];
-                    port.read_exact(&mut buf).unwrap();
-                    let control = buf[0];
+                    read_bytes(port, &mut buf, deadline)?;
+                    let control = buf[0].into();
                     let record = buf[1];
                     let group = buf[2];
                     let addr = [buf[3], buf[4], buf[5]].into();
                     let link = [buf[6], buf[7], buf[8]];
                     UpdatedLinkRecord(control, record, group, addr, link)
                 }
-                0x70 => SetNakByte(next_byte(port).unwrap()),
-                0x71 => {
+                Opcode::SetNakByte => SetNakByte(read_byte(port, deadline)?),
+                Opcode::SetAckBytes => {
                     let mut buf = [0; 2];
-                    port.read_exact(&mut buf).unwrap();
+                    read_bytes(port, &mut buf, deadline)?;
                     SetAckBytes([buf[0], buf[1]])
                 }
-                0x72 => Sleeping,
-                0x73 => {
+                Opcode::Sleep => {
+                    // The two command bytes are echoed back, but they're always zero.
+                    let mut buf = [0; 2];
+                    read_bytes(port, &mut buf, deadline)?;
+                    Sleeping
+                }
+                Opcode::GetConfig => {
                     let mut buf = [0; 3];
-                    port.read_exact(&mut buf).unwrap();
-                    let config = buf[0].into();
-                    let _ = buf[1];
-                    let _ = buf[2];
-                    GotConfig(config)
+                    read_bytes(port, &mut buf, deadline)?;
+                    GotConfig(buf[0].into(), buf[1], buf[2])
+                }
+                Opcode::CancelCleanup => CanceledCleanup,
+                Opcode::ReadDatabase => {
+                    let mut address = [0; 2];
+                    read_bytes(port, &mut address, deadline)?;
+                    ReadDatabaseBytes(address)
+                }
+                Opcode::Beep => Beeping,
+                Opcode::SetStatus => SetStatus(read_byte(port, deadline)?),
+                Opcode::SetLinkData => {
+                    let mut buf = [0; 3];
+                    read_bytes(port, &mut buf, deadline)?;
+                    SetLinkData(buf)
+                }
+                Opcode::SetRetries => SetRetries(read_byte(port, deadline)?),
+                Opcode::SetFrequencyOffset => SetFrequencyOffset(read_byte(port, deadline)?),
+                Opcode::SetTempLincAck => SetTempLincAck(read_byte(port, deadline)?),
+                Opcode::Unknown(opcode) => {
+                    unknown = Some(opcode);
+                    continue;
                 }
                 _ => {
+                    // Skip the message entirely, so that nothing inside it is mistaken for the
+                    // start of a response (or a NAK).
+                    if let Some(length) = frame_length(&[frame::START, opcode]) {
+                        let mut body = vec![0; length - 2];
+                        read_bytes(port, &mut body, deadline)?;
+                        between_frames = true;
+                    }
                     continue;
                 }
             };
+        } else if unknown.is_some() {
+            skipped += 1;
+        }
+    };
+    trace!("Decoded response {:?}", response);
+    Ok(response)
+}
+
+/// A port together with bytes that have been read from it but not yet decoded.
+struct Buffered {
+    port: Box<dyn ModemTransport>,
+    pending: VecDeque<u8>,
+    /// The most recently decoded bytes, oldest first (at most `history_capacity` of them).
+    history: VecDeque<u8>,
+    history_capacity: usize,
+}
+
+impl Buffered {
+    /// Moves every byte waiting on the port into the buffer.
+    fn fill(&mut self) -> Result<()> {
+        let mut buf = vec![0; available(&mut *self.port)?];
+        self.port.read_available(&mut buf)?;
+        self.pending.extend(buf);
+        Ok(())
+    }
+}
+
+impl Source for Buffered {
+    fn available(&mut self) -> Result<usize> {
+        Ok(self.pending.len() + available(&mut *self.port)?)
+    }
+    fn read_available(&mut self, buf: &mut [u8]) -> Result<()> {
+        let buffered = buf.len().min(self.pending.len());
+        for (byte, pending) in buf.iter_mut().zip(self.pending.drain(..buffered)) {
+            *byte = pending;
+        }
+        self.port.read_available(&mut buf[buffered..])?;
+        if self.history_capacity > 0 {
+            self.history.extend(buf.iter());
+            let excess = self.history.len().saturating_sub(self.history_capacity);
+            self.history.drain(..excess);
+        }
+        Ok(())
+    }
+    /// Abandons the frame if it's misaligned (not followed by another frame's start byte), but a
+    /// start byte and known opcode inside it begin a frame that isn't.
+    ///
+    /// Start bytes can legitimately appear inside a frame (as part of an address, say), so a
+    /// nested start byte alone isn't enough to abandon the frame.
+    fn abandon_frame(&mut self, opcode: u8, deadline: Option<Instant>) -> Result<bool> {
+        if !Opcode::from(opcode).is_known() {
+            return Ok(false);
+        }
+        let mut frame = vec![frame::START, opcode];
+        let length = loop {
+            self.fill()?;
+            frame.truncate(2);
+            frame.extend(self.pending.iter());
+            match frame_length(&frame) {
+                Some(length) if length <= frame.len() => break length,
+                _ => {
+                    let byte = read_byte(&mut *self.port, deadline)?;
+                    self.pending.push_back(byte);
+                }
+            }
+        };
+        if is_aligned(&frame, length) {
+            return Ok(false);
+        }
+        for start in 2..length {
+            let nested = &frame[start..];
+            let is_frame = nested[0] == frame::START
+                && frame_length(nested)
+                    .is_some_and(|length| length <= nested.len() && is_aligned(nested, length));
+            if is_frame {
+                self.pending.drain(..start - 2);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Reads messages and responses from a port, reporting any frames it can't make sense of.
+///
+/// Frames with unknown opcodes are skipped (just as `next_message` and `next_response` skip them),
+/// but once the reader has resynchronized on the next frame, the `on_unknown` hook (if any) is
+/// called with the unknown opcode and the number of bytes that were skipped after it.
+///
+/// The reader also recovers from truncated frames: if a frame isn't followed by the start of
+/// another, but a frame that starts inside it is, the truncated frame is dropped in favor of the
+/// one inside it. To make this possible, the reader buffers bytes it has read ahead.
+pub struct Reader {
+    source: Buffered,
+    on_unknown: Option<Box<dyn FnMut(u8, usize) + Send>>,
+    checksum_mode: ChecksumMode,
+    engines: HashMap<Address, EngineVersion>,
+    /// Whether the last read timed out partway through a frame.
+    interrupted: bool,
+}
+
+impl Reader {
+    /// Creates a reader for the given port (or other transport).
+    pub fn new<T: ModemTransport + 'static>(port: T) -> Self {
+        Self {
+            source: Buffered {
+                port: Box::new(port),
+                pending: VecDeque::new(),
+                history: VecDeque::new(),
+                history_capacity: 0,
+            },
+            on_unknown: None,
+            checksum_mode: ChecksumMode::Off,
+            engines: HashMap::new(),
+            interrupted: false,
+        }
+    }
+    /// Sets the hook to be called with each unknown opcode and the number of bytes skipped after
+    /// it.
+    pub fn on_unknown<F: FnMut(u8, usize) + Send + 'static>(mut self, hook: F) -> Self {
+        self.on_unknown = Some(Box::new(hook));
+        self
+    }
+    /// Makes the reader remember the last `capacity` bytes it has decoded (see `history`).
+    ///
+    /// By default, nothing is remembered.
+    pub fn with_history(mut self, capacity: usize) -> Self {
+        self.source.history_capacity = capacity;
+        self
+    }
+    /// The most recently decoded bytes, oldest first, if the reader was made to remember them
+    /// (see `with_history`).
+    ///
+    /// This is handy for working out what led up to an error. Bytes that have been read ahead
+    /// but not yet decoded aren't included.
+    pub fn history(&mut self) -> &[u8] {
+        self.source.history.make_contiguous()
+    }
+    /// Sets whether to check the checksums of extended messages.
+    pub fn checksum_mode(mut self, mode: ChecksumMode) -> Self {
+        self.checksum_mode = mode;
+        self
+    }
+    /// Records the engine version of the given device, for `ChecksumMode::VerifyKnownEngines`.
+    pub fn set_engine_version(&mut self, address: Address, version: EngineVersion) {
+        self.engines.insert(address, version);
+    }
+    /// The underlying port.
+    ///
+    /// Reading from the port directly skips any bytes the reader has already buffered.
+    pub fn port(&mut self) -> &mut dyn ModemTransport {
+        &mut *self.source.port
+    }
+    /// Returns the underlying port, consuming the reader (and discarding anything it has
+    /// buffered).
+    pub fn into_inner(self) -> Box<dyn ModemTransport> {
+        self.source.port
+    }
+    /// Discards everything waiting to be read, including anything the reader has buffered.
+    pub fn clear_input(&mut self) -> Result<()> {
+        self.source.pending.clear();
+        clear_input(&mut *self.source.port)
+    }
+    /// Returns the opcode of the next complete frame that has arrived, without consuming it.
+    ///
+    /// This doesn't wait; if no complete frame has arrived yet, `None` is returned. Use it to
+    /// decide between `next_message` and `next_response` before committing to either.
+    pub fn peek_opcode(&mut self) -> Result<Option<u8>> {
+        self.source.fill()?;
+        let pending = self.source.pending.make_contiguous();
+        let mut start = 0;
+        while let Some(offset) = pending[start..]
+            .iter()
+            .position(|&byte| byte == frame::START)
+        {
+            let frame = &pending[start + offset..];
+            match frame_length(frame) {
+                Some(length) if length <= frame.len() => return Ok(Some(frame[1])),
+                // Either the frame or its opcode hasn't finished arriving.
+                Some(_) => return Ok(None),
+                None if frame.len() < 2 || Opcode::from(frame[1]).is_known() => return Ok(None),
+                // Frames with unknown opcodes are skipped when decoding, so skip them here too.
+                None => start += offset + 1,
+            }
+        }
+        Ok(None)
+    }
+    /// Reads the next full message. Responses are ignored.
+    ///
+    /// An extended message that fails the checksum check (see `checksum_mode`) is consumed and
+    /// reported as `SerialError::BadChecksum`.
+    pub fn next_message(&mut self) -> Result<Message> {
+        self.message_before(None)
+    }
+    /// Reads the next full message, giving up after the given timeout. Responses are ignored.
+    ///
+    /// If the timeout strikes partway through a frame, everything waiting to be read is discarded
+    /// (see `clear_input`) before the next read, so that the rest of the frame isn't mistaken for
+    /// the start of another.
+    pub fn next_message_timeout(&mut self, timeout: Duration) -> Result<Message> {
+        self.message_before(Some(Instant::now() + timeout))
+    }
+    /// Reads the next response. Non-response messages are ignored.
+    pub fn next_response(&mut self) -> Result<Response> {
+        self.response_before(None)
+    }
+    /// Reads the next response, giving up after the given timeout (as in
+    /// `next_message_timeout`). Non-response messages are ignored.
+    pub fn next_response_timeout(&mut self, timeout: Duration) -> Result<Response> {
+        self.response_before(Some(Instant::now() + timeout))
+    }
+    /// Reads the next full message, giving up at the deadline (if any).
+    fn message_before(&mut self, deadline: Option<Instant>) -> Result<Message> {
+        self.recover_from_interruption()?;
+        let msg = match self.on_unknown {
+            Some(ref mut hook) => message_before(&mut self.source, &mut **hook, deadline),
+            None => message_before(&mut self.source, &mut |_, _| {}, deadline),
+        };
+        let msg = self.note_interruption(msg)?;
+        if let Message::Received(ref received) = msg {
+            let engine = self.engines.get(&received.from).cloned();
+            self.checksum_mode.check(received, engine)?;
+        }
+        Ok(msg)
+    }
+    /// Reads the next response, giving up at the deadline (if any).
+    fn response_before(&mut self, deadline: Option<Instant>) -> Result<Response> {
+        self.recover_from_interruption()?;
+        let response = match self.on_unknown {
+            Some(ref mut hook) => decode_response(&mut self.source, &mut **hook, deadline, None),
+            None => decode_response(&mut self.source, &mut |_, _| {}, deadline, None),
+        };
+        self.note_interruption(response)
+    }
+    /// Discards everything waiting to be read if the last read timed out partway through a frame.
+    fn recover_from_interruption(&mut self) -> Result<()> {
+        if self.interrupted {
+            self.interrupted = false;
+            self.clear_input()?;
+        }
+        Ok(())
+    }
+    /// Remembers whether the given result is a timeout partway through a frame.
+    fn note_interruption<T>(&mut self, result: Result<T>) -> Result<T> {
+        self.interrupted = matches!(result, Err(SerialError::Timeout { partial: true }));
+        result
+    }
+}
+
+/// A reader that drops the repeats of received messages.
+///
+/// Insteon devices repeat each other's messages, so the same message is often heard two or three
+/// times, each copy with one fewer hop left (or, when two devices relay it, with as many). A
+/// received message is dropped if an identical one (apart from the hops left) was heard within the
+/// window, so only the first copy is surfaced; the window should therefore be shorter than the
+/// time between deliberate repeats (such as two presses of the same button). Other messages are
+/// passed through untouched.
+pub struct DedupReader {
+    reader: Reader,
+    window: Duration,
+    /// The messages heard within the window (with their hops left cleared), along with when the
+    /// first copy was heard.
+    seen: VecDeque<(Received, Instant)>,
+}
+
+impl DedupReader {
+    /// Wraps the given reader, dropping repeats heard within the given window of the first copy.
+    pub fn new(reader: Reader, window: Duration) -> Self {
+        Self {
+            reader,
+            window,
+            seen: VecDeque::new(),
+        }
+    }
+    /// The window within which repeats are dropped.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+    /// The underlying reader.
+    pub fn reader(&mut self) -> &mut Reader {
+        &mut self.reader
+    }
+    /// Returns the underlying reader, consuming this one.
+    pub fn into_inner(self) -> Reader {
+        self.reader
+    }
+    /// Reads the next full message that isn't a repeat. Responses are ignored.
+    pub fn next_message(&mut self) -> Result<Message> {
+        loop {
+            let msg = self.reader.next_message()?;
+            if !self.is_repeat(&msg) {
+                return Ok(msg);
+            }
+        }
+    }
+    /// Reads the next full message that isn't a repeat, giving up after the given timeout.
+    /// Responses are ignored.
+    pub fn next_message_timeout(&mut self, timeout: Duration) -> Result<Message> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let msg = self.reader.next_message_timeout(remaining)?;
+            if !self.is_repeat(&msg) {
+                return Ok(msg);
+            }
+        }
+    }
+    /// Whether the given message repeats one heard within the window, remembering it either way.
+    fn is_repeat(&mut self, msg: &Message) -> bool {
+        let received = match msg {
+            Message::Received(received) => received,
+            _ => return false,
+        };
+        let now = Instant::now();
+        while let Some(&(_, heard)) = self.seen.front() {
+            if now.duration_since(heard) < self.window {
+                break;
+            }
+            self.seen.pop_front();
+        }
+        let key = Received {
+            flags: MessageFlags(received.flags.0 & !0b1100),
+            ..*received
+        };
+        if self.seen.iter().any(|(seen, _)| *seen == key) {
+            return true;
+        }
+        self.seen.push_back((key, now));
+        false
+    }
+}
+
+/// The writing half of a port whose reading half is a `Reader` (see `Modem::split`).
+///
+/// Commands are written without waiting for the modem's responses, since those arrive at the
+/// reading half.
+pub struct Writer {
+    port: Box<dyn ModemTransport>,
+}
+
+impl Writer {
+    /// Creates a writer for the given port (or other transport).
+    pub fn new<T: ModemTransport + 'static>(port: T) -> Self {
+        Self {
+            port: Box::new(port),
+        }
+    }
+    /// The underlying port.
+    pub fn port(&mut self) -> &mut dyn ModemTransport {
+        &mut *self.port
+    }
+    /// Returns the underlying port, consuming the writer.
+    pub fn into_inner(self) -> Box<dyn ModemTransport> {
+        self.port
+    }
+    /// Writes a command frame with the given opcode and payload.
+    pub fn write_frame(&mut self, opcode: Opcode, payload: &[u8]) -> Result<()> {
+        send(&mut *self.port, opcode, payload)
+    }
+    /// Sends a message to another device, as in `send_message` (but without waiting for the
+    /// modem's echo).
+    pub fn send_message(
+        &mut self,
+        to: Address,
+        flags: MessageFlags,
+        command: Command,
+        data: Option<[u8; 14]>,
+    ) -> Result<()> {
+        let payload = message_payload(to, flags, command, data);
+        self.write_frame(Opcode::SendMessage, &payload)
+    }
+}
+
+/// A handler for received Insteon messages.
+type ReceivedHandler = Box<dyn FnMut(Address, Option<Command>) + Send>;
+
+/// Routes incoming messages to handlers registered for their type.
+///
+/// Each call to `pump` reads one message and passes it to the matching handler; messages without
+/// a registered handler are dropped.
+#[derive(Default)]
+pub struct Dispatcher {
+    on_button: Option<Box<dyn FnMut(ButtonEvent) + Send>>,
+    on_x10: Option<Box<dyn FnMut(crate::x10::Message) + Send>>,
+    on_received: Option<ReceivedHandler>,
+}
+
+impl Dispatcher {
+    /// Creates a dispatcher with no handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the handler for button events on the modem.
+    pub fn on_button<F: FnMut(ButtonEvent) + Send + 'static>(mut self, handler: F) -> Self {
+        self.on_button = Some(Box::new(handler));
+        self
+    }
+    /// Sets the handler for received X10 messages.
+    pub fn on_x10<F: FnMut(crate::x10::Message) + Send + 'static>(mut self, handler: F) -> Self {
+        self.on_x10 = Some(Box::new(handler));
+        self
+    }
+    /// Sets the handler for received Insteon messages, which is passed the sender and the command
+    /// (if it's one this crate understands).
+    pub fn on_received<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(Address, Option<Command>) + Send + 'static,
+    {
+        self.on_received = Some(Box::new(handler));
+        self
+    }
+    /// Reads the next message from the port and passes it to the matching handler, if any.
+    pub fn pump(&mut self, port: &mut dyn ModemTransport) -> Result<()> {
+        match next_message(port)? {
+            Message::ButtonEvent(event) => {
+                if let Some(ref mut handler) = self.on_button {
+                    handler(event);
+                }
+            }
+            Message::X10Received(message) => {
+                if let Some(ref mut handler) = self.on_x10 {
+                    handler(message);
+                }
+            }
+            Message::Received(received) => {
+                if let Some(ref mut handler) = self.on_received {
+                    handler(received.from, received.command());
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Writes a command frame with the given opcode and payload to the port.
+fn send(port: &mut dyn ModemTransport, opcode: Opcode, payload: &[u8]) -> Result<()> {
+    let frame = frame::encode(opcode, payload);
+    trace!("Writing {:02X?}", frame);
+    Ok(port.write_all(&frame)?)
+}
+
+/// Sends the given command and reads the modem's response to it, giving up if the response
+/// doesn't arrive within the timeout.
+///
+/// The acknowledgement byte that terminates the response is consumed, and a NAK is reported as
+/// an error. Messages that arrive while waiting are discarded.
+fn request(
+    port: &mut dyn ModemTransport,
+    opcode: Opcode,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<Response> {
+    let deadline = Some(Instant::now() + timeout);
+    send(port, opcode, payload)?;
+    let response = decode_response(port, &mut |_, _| {}, deadline, Some(opcode.into()))?;
+    let status = match response {
+        // The status byte of an X10 response is decoded with it.
+        Response::SentX10(msg) => msg.status.map_or(NAK, u8::from),
+        _ => read_byte(port, deadline)?,
+    };
+    match status {
+        ACK => Ok(response),
+        _ => Err(SerialError::Nak),
+    }
+}
+
+/// Sends a frame with the given opcode and payload, returning the raw bytes of the modem's
+/// response (from the start byte up to and including the ACK or NAK).
+///
+/// This is an escape hatch for commands this crate doesn't model (such as undocumented or
+/// firmware-specific ones); prefer the dedicated senders otherwise. Responses to documented
+/// commands are read to their documented length; responses to other commands are assumed to
+/// echo the payload before adding anything of their own, and are read up to the first ACK or NAK
+/// after that. Frames that arrive before the response are discarded, and a NAK in place of the
+/// response is reported as `SerialError::Rejected`.
+pub fn send_raw(
+    port: &mut dyn ModemTransport,
+    opcode: u8,
+    payload: &[u8],
+    timeout: Duration,
+) -> Result<Vec<u8>> {
+    let deadline = Some(Instant::now() + timeout);
+    send(port, opcode.into(), payload)?;
+    let mut between_frames = true;
+    loop {
+        let byte = read_byte(port, deadline)?;
+        if between_frames && byte == NAK {
+            return Err(SerialError::Rejected(opcode));
+        }
+        between_frames = false;
+        if byte != frame::START {
+            continue;
+        }
+        let mut frame = vec![frame::START, read_byte(port, deadline)?];
+        if frame[1] == opcode {
+            break;
+        }
+        // Other frames are skipped whole (along with the ACK or NAK after a response), so that a
+        // NAK right after one is still recognized.
+        if read_frame(port, &mut frame, deadline)? {
+            if frame[1] >= 0x60 {
+                read_byte(port, deadline)?;
+            }
+            between_frames = true;
+        }
+    }
+    let mut response = vec![frame::START, opcode];
+    if read_frame(port, &mut response, deadline)? {
+        response.push(read_byte(port, deadline)?);
+        return Ok(response);
+    }
+    response.resize(2 + payload.len(), 0);
+    read_bytes(port, &mut response[2..], deadline)?;
+    loop {
+        let byte = read_byte(port, deadline)?;
+        response.push(byte);
+        if byte == ACK || byte == NAK {
+            return Ok(response);
+        }
+    }
+}
+
+/// Reads the rest of the frame whose start byte and opcode are given, returning whether its
+/// length is known (and so whether it was read at all).
+fn read_frame(
+    port: &mut dyn ModemTransport,
+    frame: &mut Vec<u8>,
+    deadline: Option<Instant>,
+) -> Result<bool> {
+    loop {
+        if let Some(length) = frame_length(frame) {
+            let read = frame.len();
+            frame.resize(length.max(read), 0);
+            read_bytes(port, &mut frame[read..], deadline)?;
+            return Ok(true);
+        }
+        if !Opcode::from(frame[1]).is_known() {
+            return Ok(false);
+        }
+        // The length of some frames depends on bytes within them (such as the message flags).
+        frame.push(read_byte(port, deadline)?);
+    }
+}
+
+/// Makes the modem beep.
+pub fn beep(port: &mut dyn ModemTransport, timeout: Duration) -> Result<()> {
+    match request(port, Opcode::Beep, &[], timeout)? {
+        Response::Beeping => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Sets the modem's status byte.
+pub fn set_status(port: &mut dyn ModemTransport, status: u8, timeout: Duration) -> Result<()> {
+    match request(port, Opcode::SetStatus, &[status], timeout)? {
+        Response::SetStatus(byte) if byte == status => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Sets the link data (the three bytes stored in the modem's record) for the next ALL-Link the
+/// modem makes. Only RF modems support this.
+pub fn set_link_data(
+    port: &mut dyn ModemTransport,
+    data: [u8; 3],
+    timeout: Duration,
+) -> Result<()> {
+    match request(port, Opcode::SetLinkData, &data, timeout)? {
+        Response::SetLinkData(set) if set == data => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Sets how many times the modem retries messages while making new ALL-Links. Only RF modems
+/// support this.
+pub fn set_retries(port: &mut dyn ModemTransport, retries: u8, timeout: Duration) -> Result<()> {
+    match request(port, Opcode::SetRetries, &[retries], timeout)? {
+        Response::SetRetries(set) if set == retries => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Sets the RF frequency offset of the modem's radio. Only RF modems support this.
+pub fn set_frequency_offset(
+    port: &mut dyn ModemTransport,
+    offset: u8,
+    timeout: Duration,
+) -> Result<()> {
+    match request(port, Opcode::SetFrequencyOffset, &[offset], timeout)? {
+        Response::SetFrequencyOffset(set) if set == offset => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Sets the acknowledgement the modem sends for TempLinc commands. Only RF modems support this.
+pub fn set_temp_linc_ack(port: &mut dyn ModemTransport, byte: u8, timeout: Duration) -> Result<()> {
+    match request(port, Opcode::SetTempLincAck, &[byte], timeout)? {
+        Response::SetTempLincAck(set) if set == byte => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Sets the byte the modem sends as the second command byte of its ACKs to direct messages.
+///
+/// The modem acknowledges direct messages from other devices by itself, so this is how the host
+/// answers direct commands whose reply carries a value in the ACK, such as a status request
+/// (`Command::StatusRequest(0x00)`, answered with the host's on level) or a request for its operating
+/// flags (`0x1F`). Set the byte before the command arrives.
+pub fn set_ack_byte(port: &mut dyn ModemTransport, byte: u8, timeout: Duration) -> Result<()> {
+    match request(port, Opcode::SetAckByte, &[byte], timeout)? {
+        Response::SetAckByte(set) if set == byte => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Sets both command bytes the modem sends in its ACKs to direct messages.
+///
+/// This is like `set_ack_byte`, but for direct commands whose reply carries a value in both
+/// command bytes.
+pub fn set_ack_bytes(
+    port: &mut dyn ModemTransport,
+    bytes: [u8; 2],
+    timeout: Duration,
+) -> Result<()> {
+    match request(port, Opcode::SetAckBytes, &bytes, timeout)? {
+        Response::SetAckBytes(set) if set == bytes => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Sets the byte the modem sends as the second command byte of its NAKs to direct messages.
+///
+/// NAKs carry the reason for the refusal in this byte (e.g. `0xFF` for a sender that isn't linked
+/// to the modem, or `0xFD` for an unknown command), so set it before refusing a command.
+pub fn set_nak_byte(port: &mut dyn ModemTransport, byte: u8, timeout: Duration) -> Result<()> {
+    match request(port, Opcode::SetNakByte, &[byte], timeout)? {
+        Response::SetNakByte(set) if set == byte => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Gets the modem's address, category, and firmware version.
+pub fn get_info(
+    port: &mut dyn ModemTransport,
+    timeout: Duration,
+) -> Result<(Address, Category, Option<Firmware>)> {
+    match request(port, Opcode::GetInfo, &[], timeout)? {
+        Response::GotInfo(address, category, firmware) => Ok((address, category, firmware)),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Gets the modem's configuration.
+pub fn get_config(port: &mut dyn ModemTransport, timeout: Duration) -> Result<Config> {
+    match request(port, Opcode::GetConfig, &[], timeout)? {
+        Response::GotConfig(config, ..) => Ok(config),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Gets the modem's ALL-Link database delta, which changes whenever the database does.
+///
+/// This is read from the Get IM Configuration response, so it's only meaningful on firmware that
+/// reports it there (see `Response::GotConfig`).
+pub fn database_delta(port: &mut dyn ModemTransport, timeout: Duration) -> Result<u8> {
+    let response = request(port, Opcode::GetConfig, &[], timeout)?;
+    response
+        .database_delta()
+        .ok_or(SerialError::UnexpectedResponse(response))
+}
+
+/// Puts an RF modem to sleep.
+///
+/// The modem won't respond to anything until it's woken up again (see `wake`).
+pub fn sleep(port: &mut dyn ModemTransport, timeout: Duration) -> Result<()> {
+    match request(port, Opcode::Sleep, &[0x00, 0x00], timeout)? {
+        Response::Sleeping => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Wakes a sleeping RF modem.
+///
+/// Any byte wakes the modem (and is then discarded), so this sends a dummy byte and then waits
+/// for the modem to answer a configuration request to confirm that it's listening again.
+pub fn wake(port: &mut dyn ModemTransport, timeout: Duration) -> Result<()> {
+    port.write_all(&[0x00])?;
+    get_config(port, timeout).map(|_| ())
+}
+
+/// Finds, adds, modifies, or deletes a record in the modem's ALL-Link database.
+///
+/// The modem NAKs a find or delete that matches no record, which is reported as
+/// `SerialError::Nak`.
+pub fn manage_link_record(
+    port: &mut dyn ModemTransport,
+    control: LinkRecordControl,
+    flags: u8,
+    group: Group,
+    address: Address,
+    data: [u8; 3],
+    timeout: Duration,
+) -> Result<Response> {
+    let payload = [
+        control.into(),
+        flags,
+        group,
+        address[0],
+        address[1],
+        address[2],
+        data[0],
+        data[1],
+        data[2],
+    ];
+    match request(port, Opcode::ManageLinkRecord, &payload, timeout)? {
+        response @ Response::UpdatedLinkRecord(..) => Ok(response),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Sends a message to another device.
+///
+/// If `data` is given, the message is sent as an extended message (and the extended flag is set
+/// automatically); otherwise, it's sent as a standard message. Commands that can only be sent as
+/// extended messages (see `Command::is_extended`) are sent with empty data (and a checksum) if
+/// none is given.
+///
+/// Only the modem's echo is awaited. The device's own acknowledgement (if any) arrives later as a
+/// `Message::Received`.
+pub fn send_message(
+    port: &mut dyn ModemTransport,
+    to: Address,
+    flags: MessageFlags,
+    command: Command,
+    data: Option<[u8; 14]>,
+    timeout: Duration,
+) -> Result<()> {
+    let payload = message_payload(to, flags, command, data);
+    match request(port, Opcode::SendMessage, &payload, timeout)? {
+        Response::SentMessage(..) => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Builds the payload of a send message command (see `send_message`).
+fn message_payload(
+    to: Address,
+    flags: MessageFlags,
+    command: Command,
+    data: Option<[u8; 14]>,
+) -> Vec<u8> {
+    let data = match data {
+        None if command.is_extended() => Some(ExtendedMessage::new(command, [0; 13]).data),
+        data => data,
+    };
+    let flags = if data.is_some() {
+        flags.0 | MessageFlags::EXTENDED
+    } else {
+        flags.0 & !MessageFlags::EXTENDED
+    };
+    let [cmd1, cmd2]: [u8; 2] = command.into();
+    let mut payload = vec![to[0], to[1], to[2], flags, cmd1, cmd2];
+    if let Some(data) = data {
+        payload.extend_from_slice(&data);
+    }
+    payload
+}
+
+/// Sends an ALL-Link command to every member of the given group.
+///
+/// Only the modem's echo is awaited. The modem then follows up with each member (an ALL-Link
+/// cleanup), reporting members that don't answer as `Message::LinkCleanupFailed` and finishing
+/// with `Message::LinkCleanupStatus`; see `Modem::scene_on` for a way to collect these.
+pub fn send_link_command(
+    port: &mut dyn ModemTransport,
+    group: Group,
+    command: Command,
+    timeout: Duration,
+) -> Result<()> {
+    let [cmd1, cmd2]: [u8; 2] = command.into();
+    match request(port, Opcode::SendLinkCommand, &[group, cmd1, cmd2], timeout)? {
+        Response::SentLinkCommand(sent, ..) if sent == group => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Reads the ALL-Link database record at the given memory address.
+pub fn read_database(
+    port: &mut dyn ModemTransport,
+    address: [u8; 2],
+    timeout: Duration,
+) -> Result<DatabaseRecord> {
+    match request(port, Opcode::ReadDatabase, &address, timeout)? {
+        Response::ReadDatabaseBytes(read) if read == address => {}
+        response => return Err(SerialError::UnexpectedResponse(response)),
+    }
+    let deadline = Some(Instant::now() + timeout);
+    loop {
+        match message_before(port, &mut |_, _| {}, deadline)? {
+            Message::DatabaseRecordFound(record) if record.address == address => return Ok(record),
+            _ => continue,
+        }
+    }
+}
+
+/// Reads every record in the modem's ALL-Link database, in order.
+///
+/// The modem NAKs the request for the first record if the database is empty and the request for
+/// the next record once the last one has been read; both simply end the walk.
+pub fn read_link_records(
+    port: &mut dyn ModemTransport,
+    timeout: Duration,
+) -> Result<Vec<LinkEntry>> {
+    let mut records = Vec::new();
+    let mut opcode = Opcode::GetFirstLinkRecord;
+    loop {
+        match request(port, opcode, &[], timeout) {
+            Ok(Response::GotFirstLinkRecord) | Ok(Response::GotNextLinkRecord) => {}
+            Err(SerialError::Nak) => return Ok(records),
+            Ok(response) => return Err(SerialError::UnexpectedResponse(response)),
+            Err(e) => return Err(e),
         }
+        // The record itself follows the response as a separate message.
+        let deadline = Some(Instant::now() + timeout);
+        let record = loop {
+            if let Message::LinkRecordResponse(flags, group, id, data) =
+                message_before(port, &mut |_, _| {}, deadline)?
+            {
+                break LinkEntry {
+                    flags,
+                    group,
+                    id,
+                    data,
+                };
+            }
+        };
+        records.push(record);
+        opcode = Opcode::GetNextLinkRecord;
+    }
+}
+
+/// Sets the modem's own device category (and, optionally, firmware version).
+///
+/// This lets the modem present itself as a particular kind of device when linking.
+pub fn set_category<C: Into<Category>>(
+    port: &mut dyn ModemTransport,
+    category: C,
+    firmware: Option<Firmware>,
+    timeout: Duration,
+) -> Result<Response> {
+    let category = category.into();
+    let payload = [
+        category.category,
+        category.subcategory,
+        firmware.map_or(0x00, |firmware| firmware.0),
+    ];
+    match request(port, Opcode::SetCategory, &payload, timeout)? {
+        response @ Response::SetCategory(..) => Ok(response),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Sends a single X10 frame (either half of an X10 transmission; see `Modem::send_x10`), waiting
+/// for the modem to echo it.
+///
+/// Unit codes outside 1–16 can't be encoded and are reported as an `InvalidInput` I/O error.
+pub fn send_x10(
+    port: &mut dyn ModemTransport,
+    house: HouseCode,
+    payload: Payload,
+    timeout: Duration,
+) -> Result<()> {
+    let (low, flag) = match payload.to_nibble_and_flag() {
+        Some(encoded) => encoded,
+        None => {
+            let message = format!("Invalid X10 payload {:?}", payload);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
+    };
+    let raw = house.to_nibble() << 4 | low;
+    match request(port, Opcode::SendX10, &[raw, flag], timeout)? {
+        Response::SentX10(msg) if msg.house == house && msg.payload == payload => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Puts the modem into linking mode in the given role for the given group.
+///
+/// Starting in the `LinkRole::Deleted` role deletes the link instead.
+///
+/// The modem stays in linking mode until a link completes or `cancel_linking` is called. See
+/// `begin_linking_session` for a way to make sure the latter happens.
+pub fn start_linking(
+    port: &mut dyn ModemTransport,
+    role: LinkRole,
+    group: Group,
+    timeout: Duration,
+) -> Result<()> {
+    match request(port, Opcode::StartLinking, &[role.into(), group], timeout)? {
+        Response::StartedLink(..) => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Takes the modem out of linking mode.
+pub fn cancel_linking(port: &mut dyn ModemTransport, timeout: Duration) -> Result<()> {
+    match request(port, Opcode::CancelLinking, &[], timeout)? {
+        Response::CanceledLink => Ok(()),
+        response => Err(SerialError::UnexpectedResponse(response)),
+    }
+}
+
+/// Puts the modem into linking mode (as in `start_linking`), returning a session that takes the
+/// modem back out of linking mode when dropped unless the link has completed.
+pub fn begin_linking_session(
+    port: &mut dyn ModemTransport,
+    role: LinkRole,
+    group: Group,
+    timeout: Duration,
+) -> Result<LinkSession<'_>> {
+    start_linking(port, role, group, timeout)?;
+    Ok(LinkSession {
+        port,
+        timeout,
+        completed: false,
     })
 }
+
+/// An in-progress linking session.
+///
+/// If the session is dropped before a link completes, linking is canceled (using the timeout the
+/// session was begun with).
+pub struct LinkSession<'a> {
+    port: &'a mut dyn ModemTransport,
+    timeout: Duration,
+    completed: bool,
+}
+
+impl<'a> LinkSession<'a> {
+    /// Waits for the link to complete, returning the result.
+    ///
+    /// Other messages that arrive in the meantime are discarded.
+    pub fn wait_for_completion(&mut self, timeout: Duration) -> Result<LinkResult> {
+        let deadline = Some(Instant::now() + timeout);
+        loop {
+            if let Message::LinkComplete(result) =
+                message_before(&mut *self.port, &mut |_, _| {}, deadline)?
+            {
+                self.completed = true;
+                return Ok(result);
+            }
+        }
+    }
+    /// Cancels linking (if the link hasn't already completed), reporting any error.
+    pub fn cancel(mut self) -> Result<()> {
+        self.completed = true;
+        cancel_linking(&mut *self.port, self.timeout)
+    }
+}
+
+impl<'a> Drop for LinkSession<'a> {
+    fn drop(&mut self) {
+        if !self.completed {
+            let _ = cancel_linking(&mut *self.port, self.timeout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockPort;
+
+    const FRAME: [u8; 11] = [
+        0x02, 0x50, 0xAA, 0xBB, 0xCC, 0x44, 0x55, 0x66, 0x0F, 0x11, 0xFF,
+    ];
+
+    #[test]
+    fn cleanup_failure() {
+        let mut port = MockPort::new(&[0x02, 0x56, 0x01, 0x03, 0x11, 0x22, 0x33]);
+        let msg = next_message(&mut port).unwrap();
+        assert!(msg.is_cleanup_failure());
+        match msg {
+            Message::LinkCleanupFailed(group, address) => {
+                assert_eq!(group, 0x03);
+                assert_eq!(address, Address::from([0x11, 0x22, 0x33]));
+            }
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+        assert_eq!(port.remaining(), 0);
+    }
+
+    #[test]
+    fn clear_input_after_timeout() {
+        let mut port = MockPort::new(&[0x02, 0x50, 0x11, 0x22, 0x33]);
+        match next_message_timeout(&mut port, Duration::from_millis(10)) {
+            Err(SerialError::Timeout { partial: true }) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        // The rest of the frame shows up late, and happens to look like a user reset.
+        port.push(&[0x02, 0x55, 0x0F, 0x11, 0xFF]);
+        clear_input(&mut port).unwrap();
+        port.push(&FRAME);
+        match next_message(&mut port).unwrap() {
+            Message::Received(msg) => assert_eq!(msg.from, Address::from([0xAA, 0xBB, 0xCC])),
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn drain_truncated_frame() {
+        let mut port = MockPort::new(&[&FRAME[..], &FRAME[..5]].concat());
+        let messages = drain_messages(&mut port).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(port.remaining(), 0);
+        // A lone start byte doesn't hang the drain either.
+        port.push(&[frame::START]);
+        assert!(drain_messages(&mut port).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_empty_link_database() {
+        let mut port = MockPort::new(&[0x02, 0x69, 0x15]);
+        let records = read_link_records(&mut port, Duration::from_secs(1)).unwrap();
+        assert!(records.is_empty());
+        assert_eq!(port.output, frame::encode(Opcode::GetFirstLinkRecord, &[]));
+    }
+
+    #[test]
+    fn read_link_database() {
+        let frames = [
+            frame::encode(Opcode::GetFirstLinkRecord, &[ACK]),
+            frame::encode(
+                Opcode::LinkRecordResponse,
+                &[0xE2, 0x01, 0x11, 0x22, 0x33, 0x01, 0x20, 0x41],
+            ),
+            frame::encode(Opcode::GetNextLinkRecord, &[ACK]),
+            frame::encode(
+                Opcode::LinkRecordResponse,
+                &[0xA2, 0x02, 0x44, 0x55, 0x66, 0xFF, 0x1C, 0x01],
+            ),
+            // The modem NAKs the request for the next record once there are no more.
+            frame::encode(Opcode::GetNextLinkRecord, &[0x15]),
+        ];
+        let mut port = MockPort::new(&frames.concat());
+        let records = read_link_records(&mut port, Duration::from_secs(1)).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].group, 0x01);
+        assert_eq!(records[0].id, Address::from([0x11, 0x22, 0x33]));
+        assert_eq!(records[1].flags, 0xA2);
+        assert_eq!(records[1].data, LinkData::from([0xFF, 0x1C, 0x01]));
+        let expected = [
+            frame::encode(Opcode::GetFirstLinkRecord, &[]),
+            frame::encode(Opcode::GetNextLinkRecord, &[]),
+            frame::encode(Opcode::GetNextLinkRecord, &[]),
+        ];
+        assert_eq!(port.output, expected.concat());
+    }
+
+    #[test]
+    fn reader_resyncs_after_truncated_frame() {
+        let mut bytes = vec![0x02, 0x50, 0x11, 0x22, 0x33];
+        bytes.extend_from_slice(&FRAME);
+        let mut reader = Reader::new(Box::new(MockPort::new(&bytes)));
+        match reader.next_message().unwrap() {
+            Message::Received(msg) => {
+                assert_eq!(msg.from, Address::from([0xAA, 0xBB, 0xCC]));
+                assert_eq!(msg.cmd1, 0x11);
+            }
+            msg => panic!("Unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn reader_peek_opcode() {
+        let mut bytes = vec![0x06, 0x02, 0x77, 0x06];
+        bytes.extend_from_slice(&FRAME);
+        let mut reader = Reader::new(Box::new(MockPort::new(&bytes)));
+        assert_eq!(reader.peek_opcode().unwrap(), Some(0x77));
+        assert_eq!(reader.peek_opcode().unwrap(), Some(0x77));
+        assert_eq!(reader.next_response().unwrap(), Response::Beeping);
+        assert_eq!(reader.peek_opcode().unwrap(), Some(0x50));
+    }
+
+    #[test]
+    fn reader_keeps_frames_containing_start_bytes() {
+        // The sender's address contains what looks like a user reset frame (02 55).
+        let mut bytes = vec![
+            0x02, 0x50, 0x11, 0x02, 0x55, 0x44, 0x55, 0x66, 0x0F, 0x11, 0xFF,
+        ];
+        bytes.extend_from_slice(&FRAME);
+        let mut reader = Reader::new(Box::new(MockPort::new(&bytes)));
+        for from in &[[0x11, 0x02, 0x55], [0xAA, 0xBB, 0xCC]] {
+            match reader.next_message().unwrap() {
+                Message::Received(msg) => assert_eq!(msg.from, Address::from(*from)),
+                msg => panic!("Unexpected message {:?}", msg),
+            }
+        }
+    }
+
+    #[test]
+    fn reader_checks_known_engines() {
+        // An extended message from 11.22.33 with a zero checksum (which should be 0xD2).
+        let mut payload = vec![0x11, 0x22, 0x33, 0xAA, 0xBB, 0xCC, 0x1F, 0x2E, 0x00];
+        payload.extend_from_slice(&[0; 14]);
+        let frame = frame::encode(Opcode::ExtendedMessageReceived, &payload);
+        let port = MockPort::new(&[&frame[..], &frame[..]].concat());
+        let mut reader =
+            Reader::new(Box::new(port)).checksum_mode(ChecksumMode::VerifyKnownEngines);
+        assert!(reader.next_message().is_ok());
+        reader.set_engine_version([0x11, 0x22, 0x33].into(), EngineVersion::I2cs);
+        match reader.next_message() {
+            Err(SerialError::BadChecksum(msg)) => assert_eq!(msg.cmd1, 0x2E),
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn frames_consume_documented_lengths() {
+        // Each opcode with a payload of the length given in the modem developer's guide (not
+        // counting the ACK or NAK that follows responses).
+        let x10 = [0x66, 0x00];
+        let mut extended = vec![0x11, 0x22, 0x33, 0x1F, 0x2E, 0x00];
+        extended.extend_from_slice(&[0; 14]);
+        let messages: &[(u8, &[u8])] = &[
+            (0x50, &[0; 9]),
+            (0x51, &[0; 23]),
+            (0x52, &x10),
+            (0x53, &[0; 8]),
+            (0x54, &[0x02]),
+            (0x55, &[]),
+            (0x56, &[0x01, 0x01, 0x11, 0x22, 0x33]),
+            (0x57, &[0; 8]),
+            (0x58, &[0x06]),
+            (0x59, &[0; 10]),
+        ];
+        let responses: &[(u8, &[u8])] = &[
+            (0x60, &[0; 6]),
+            (0x61, &[0; 3]),
+            (0x62, &[0x11, 0x22, 0x33, 0x0F, 0x11, 0xFF]),
+            (0x62, &extended),
+            (0x63, &x10),
+            (0x64, &[0x01, 0x01]),
+            (0x65, &[]),
+            (0x66, &[0; 3]),
+            (0x67, &[]),
+            (0x68, &[0]),
+            (0x69, &[]),
+            (0x6A, &[]),
+            (0x6B, &[0]),
+            (0x6C, &[]),
+            (0x6D, &[]),
+            (0x6E, &[]),
+            (0x6F, &[0; 9]),
+            (0x70, &[0]),
+            (0x71, &[0; 2]),
+            (0x72, &[0; 2]),
+            (0x73, &[0; 3]),
+            (0x74, &[]),
+            (0x75, &[0; 2]),
+            (0x77, &[]),
+            (0x78, &[0]),
+            (0x79, &[0; 3]),
+            (0x7A, &[0]),
+            (0x7B, &[0]),
+            (0x7C, &[0]),
+        ];
+        // A byte that isn't a start byte, which must be left for the next read.
+        let sentinel = [0x06];
+        for &(opcode, payload) in messages {
+            let frame = frame::encode(opcode.into(), payload);
+            let mut port = MockPort::new(&[&frame[..], &sentinel].concat());
+            next_message(&mut port).unwrap();
+            assert_eq!(port.remaining(), 1, "Opcode {:#04x}", opcode);
+            assert_eq!(frame_length(&frame), Some(frame.len()));
+        }
+        for &(opcode, payload) in responses {
+            let frame = frame::encode(opcode.into(), payload);
+            let mut port = MockPort::new(&[&frame[..], &sentinel].concat());
+            next_response(&mut port).unwrap();
+            // X10 responses hold the status byte, so they take the sentinel as theirs.
+            let remaining = if opcode == 0x63 { 0 } else { 1 };
+            assert_eq!(port.remaining(), remaining, "Opcode {:#04x}", opcode);
+            assert_eq!(frame_length(&frame), Some(frame.len()));
+        }
+    }
+
+    #[test]
+    fn x10_response_status() {
+        use crate::x10::{Ack, Command};
+        // Command On for house code A, refused by the modem.
+        let (response, consumed) = Response::parse(&[0x02, 0x63, 0x62, 0x80, 0x15]).unwrap();
+        match response {
+            Response::SentX10(msg) => assert_eq!(msg.status, Some(Ack::Nak)),
+            response => panic!("Unexpected response {:?}", response),
+        }
+        assert_eq!(consumed, 5);
+        let mut port = MockPort::new(&[0x02, 0x63, 0x62, 0x80, 0x15]);
+        let payload = Payload::Command(Command::On);
+        match send_x10(&mut port, HouseCode::A, payload, Duration::from_millis(10)) {
+            Err(SerialError::Nak) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        assert_eq!(port.remaining(), 0);
+    }
+
+    #[test]
+    fn send_raw_reads_through_ack() {
+        let mut bytes = FRAME.to_vec();
+        bytes.extend_from_slice(&[0x02, 0x78, 0x06, 0x06]);
+        let mut port = MockPort::new(&bytes);
+        let response = send_raw(&mut port, 0x78, &[0x06], Duration::from_millis(10)).unwrap();
+        assert_eq!(response, [0x02, 0x78, 0x06, 0x06]);
+        assert_eq!(port.output, [0x02, 0x78, 0x06]);
+        // The modem's info holds ACK bytes, which don't end the response early.
+        let info = [0x02, 0x60, 0x11, 0x22, 0x06, 0x01, 0x06, 0x15, 0x06];
+        let mut port = MockPort::new(&info);
+        let response = send_raw(&mut port, 0x60, &[], Duration::from_millis(10)).unwrap();
+        assert_eq!(response, info);
+        assert_eq!(port.remaining(), 0);
+        // Responses to unknown commands are read through the first ACK after the echo.
+        let mut port = MockPort::new(&[0x02, 0x7F, 0x06, 0x42, 0x06]);
+        let response = send_raw(&mut port, 0x7F, &[0x06], Duration::from_millis(10)).unwrap();
+        assert_eq!(response, [0x02, 0x7F, 0x06, 0x42, 0x06]);
+    }
+
+    #[test]
+    fn send_raw_bare_nak() {
+        // A message arrives first, then the modem NAKs the command without echoing it.
+        let mut port = MockPort::new(&[&FRAME[..], &[NAK]].concat());
+        match send_raw(&mut port, 0x60, &[], Duration::from_millis(10)) {
+            Err(SerialError::Rejected(0x60)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn bare_nak_is_rejection() {
+        // A message arrives first, then the modem NAKs the command without echoing it.
+        let mut bytes = FRAME.to_vec();
+        bytes.push(NAK);
+        let mut port = MockPort::new(&bytes);
+        match set_status(&mut port, 0x01, Duration::from_millis(10)) {
+            Err(SerialError::Rejected(0x78)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        let mut port = MockPort::new(&[0x02, 0x78, 0x01, NAK]);
+        match set_status(&mut port, 0x01, Duration::from_millis(10)) {
+            Err(SerialError::Nak) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn next_button_event_skips_messages() {
+        let mut bytes = FRAME.to_vec();
+        bytes.extend_from_slice(&[0x02, 0x54, 0x03]);
+        let mut port = MockPort::new(&bytes);
+        let event = next_button_event(&mut port).unwrap();
+        assert_eq!(Some(event), ButtonEvent::from_im_code(0x03));
+    }
+
+    #[test]
+    fn reader_history() {
+        let bytes = [&FRAME[..], &FRAME[..]].concat();
+        let mut reader = Reader::new(Box::new(MockPort::new(&bytes))).with_history(4);
+        reader.next_message().unwrap();
+        assert_eq!(reader.history(), &FRAME[7..]);
+        let mut reader = Reader::new(Box::new(MockPort::new(&bytes)));
+        reader.next_message().unwrap();
+        assert!(reader.history().is_empty());
+    }
+
+    #[test]
+    fn parse_slices() {
+        let mut bytes = vec![0x02, 0x77, 0x06];
+        bytes.extend_from_slice(&FRAME);
+        let (msg, consumed) = Message::parse(&bytes).unwrap();
+        assert_eq!(msg.sender(), Some(Address::from([0xAA, 0xBB, 0xCC])));
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(Response::parse(&bytes).unwrap(), (Response::Beeping, 2));
+        match Message::parse(&FRAME[..10]) {
+            Err(SerialError::Incomplete) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn to_bytes_round_trip() {
+        let frames: &[&[u8]] = &[
+            &FRAME,
+            &[
+                0x02, 0x51, 0x11, 0x22, 0x33, 0xAA, 0xBB, 0xCC, 0x10, 0x2E, 0x00, 0x01, 0x02, 0x03,
+                0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0xC2,
+            ],
+            &[0x02, 0x52, 0x66, 0x00],
+            &[0x02, 0x52, 0x62, 0x80],
+            &[0x02, 0x53, 0x01, 0x01, 0x11, 0x22, 0x33, 0x02, 0x1A, 0x41],
+            &[0x02, 0x54, 0x02],
+            &[0x02, 0x55],
+            &[0x02, 0x56, 0x01, 0x05, 0x11, 0x22, 0x33],
+            &[0x02, 0x57, 0xE2, 0x01, 0x11, 0x22, 0x33, 0x01, 0x20, 0x41],
+            &[0x02, 0x58, 0x15],
+            &[
+                0x02, 0x59, 0x0F, 0xF8, 0xA2, 0x00, 0x11, 0x22, 0x33, 0xFF, 0x1C, 0x01,
+            ],
+        ];
+        for frame in frames {
+            let (msg, _) = Message::parse(frame).unwrap();
+            assert_eq!(msg.to_bytes(), *frame);
+            assert_eq!(Message::parse(&msg.to_bytes()).unwrap(), (msg, frame.len()));
+        }
+    }
+
+    /// Reads every message a `DedupReader` surfaces from broadcasts with the given flags, returning
+    /// the hops left of each.
+    fn dedup(flags: &[u8], window: Duration) -> Vec<u8> {
+        let mut input = Vec::new();
+        for &flags in flags {
+            input.extend_from_slice(&[0x02, 0x50, 0x11, 0x22, 0x33, 0x00, 0x00, 0x01, flags]);
+            input.extend_from_slice(&[0x11, 0x00]);
+        }
+        let reader = Reader::new(Box::new(MockPort::new(&input)));
+        let mut reader = DedupReader::new(reader, window);
+        let mut hops_left = Vec::new();
+        loop {
+            match reader.next_message_timeout(Duration::from_millis(10)) {
+                Ok(Message::Received(msg)) => hops_left.push(msg.flags.hops_left()),
+                Err(SerialError::Timeout { partial: false }) => return hops_left,
+                result => panic!("Unexpected result {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn dedup_reader() {
+        let window = Duration::from_secs(60);
+        // A broadcast with three hops left and its repeat with two.
+        assert_eq!(dedup(&[0xCF, 0xCB], window), [3]);
+        // A second relay heard with as many hops left.
+        assert_eq!(dedup(&[0xCB, 0xCB], window), [2]);
+        // A copy with more hops left than the first one heard.
+        assert_eq!(dedup(&[0xCB, 0xCF], window), [2]);
+        // Outside the window, every copy is surfaced.
+        assert_eq!(dedup(&[0xCF, 0xCB], Duration::from_secs(0)), [3, 2]);
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_frames_decode() {
+        use crate::message::frame::ArbitraryFrames;
+        use arbitrary::{Arbitrary, Unstructured};
+        // A fixed xorshift sequence stands in for the fuzzer's input.
+        let mut state = 0x2545_F491u32;
+        let noise: Vec<u8> = (0..1 << 16)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        for chunk in noise.chunks(256) {
+            let frames = ArbitraryFrames::arbitrary(&mut Unstructured::new(chunk)).unwrap();
+            let mut rest = &frames.0[..];
+            while let Ok((_, consumed)) = Message::parse(rest) {
+                rest = &rest[consumed..];
+            }
+            let _ = Response::parse(&frames.0);
+        }
+    }
+
+    #[test]
+    fn config_delta() {
+        // Monitor mode on and auto-linking off (its bit is inverted), with a database delta of 0x2A.
+        let frame = [0x02, 0x73, 0xC0, 0x2A, 0x00, 0x06];
+        let (response, _) = Response::parse(&frame).unwrap();
+        assert_eq!(response.database_delta(), Some(0x2A));
+        let mut port = MockPort::new(&frame);
+        assert_eq!(
+            database_delta(&mut port, Duration::from_millis(10)).unwrap(),
+            0x2A
+        );
+    }
+
+    #[test]
+    fn sync_discards_partial_frames() {
+        // The tail of a standard message, with what looks like the start of a frame inside it.
+        let mut port = MockPort::new(&[0x33, 0x02, 0x50, 0xCF, 0x11, 0x00]);
+        sync(&mut port, Duration::from_secs(1)).unwrap();
+        assert_eq!(port.remaining(), 0);
+        port.push(&[0x02, 0x55]);
+        assert_eq!(next_message(&mut port).unwrap(), Message::UserResetDetected);
+    }
+
+    #[test]
+    fn partial_timeouts() {
+        let port = MockPort::new(&[0x02, 0x50, 0x11, 0x22, 0x33]);
+        let mut reader = Reader::new(Box::new(port));
+        match reader.next_message_timeout(Duration::from_millis(10)) {
+            Err(SerialError::Timeout { partial: true }) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        let mut port = MockPort::new(&[]);
+        match next_message_timeout(&mut port, Duration::from_millis(10)) {
+            Err(SerialError::Timeout { partial: false }) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+}
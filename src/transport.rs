@@ -0,0 +1,47 @@
+//! Connections over which a modem can be reached.
+
+use std::io::{self, Read, Write};
+
+use serialport::{ClearBuffer, SerialPort};
+
+/// A connection to a modem, over which frames are read and written.
+///
+/// The utilities in `serial` only need to read and write bytes and to check how many are waiting,
+/// so anything that can do that can stand in for a serial port by implementing this trait (a TCP
+/// connection to a serial bridge such as `ser2net`, for instance; see `open_tcp`). Serial ports
+/// implement it as `Box<dyn SerialPort>`.
+pub trait ModemTransport: Read + Write + Send {
+    /// Returns the number of bytes that can be read without waiting.
+    fn bytes_to_read(&mut self) -> io::Result<usize>;
+    /// Discards everything received but not yet read.
+    fn clear_input(&mut self) -> io::Result<()>;
+    /// Returns another handle to the same connection, so that it can be read from one thread and
+    /// written from another.
+    ///
+    /// Connections that can't be shared this way return an error.
+    fn try_clone(&self) -> io::Result<Box<dyn ModemTransport>>;
+}
+
+impl ModemTransport for dyn SerialPort {
+    fn bytes_to_read(&mut self) -> io::Result<usize> {
+        Ok(SerialPort::bytes_to_read(self)? as usize)
+    }
+    fn clear_input(&mut self) -> io::Result<()> {
+        Ok(self.clear(ClearBuffer::Input)?)
+    }
+    fn try_clone(&self) -> io::Result<Box<dyn ModemTransport>> {
+        Ok(Box::new(SerialPort::try_clone(self)?))
+    }
+}
+
+impl<T: ModemTransport + ?Sized> ModemTransport for Box<T> {
+    fn bytes_to_read(&mut self) -> io::Result<usize> {
+        (**self).bytes_to_read()
+    }
+    fn clear_input(&mut self) -> io::Result<()> {
+        (**self).clear_input()
+    }
+    fn try_clone(&self) -> io::Result<Box<dyn ModemTransport>> {
+        (**self).try_clone()
+    }
+}
@@ -1,9 +1,45 @@
 //! Utilities for X10 messages over Insteon's network.
 
-use std::fmt;
+use alloc::string::{String, ToString};
+use core::{convert::TryFrom, fmt, str::FromStr};
+
+/// An error encountered while decoding part of an X10 message.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DecodeError {
+    /// The given value doesn't fit in a nibble.
+    InvalidNibble(u8),
+    /// The flag byte was neither `0x00` (unit code) nor `0x80` (command).
+    InvalidFlag(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidNibble(byte) => write!(f, "{:#04x} is not a nibble", byte),
+            DecodeError::InvalidFlag(byte) => write!(f, "Invalid X10 flag byte {:#04x}", byte),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// An error returned when parsing a house code from a character or string.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ParseHouseCodeError(pub String);
+
+impl fmt::Display for ParseHouseCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} is not an X10 house code (A–P)", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseHouseCodeError {}
 
 /// The house code for the X10 message (A–P).
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum HouseCode {
     A,
     B,
@@ -26,33 +62,100 @@ pub enum HouseCode {
 impl HouseCode {
     /// Attempts to convert the argument to a house code.
     pub fn try_from<T: Into<u8>>(byte: T) -> Option<Self> {
-        use self::HouseCode::*;
-        match byte.into() {
-            0x6 => Some(A),
-            0xE => Some(B),
-            0x2 => Some(C),
-            0xA => Some(D),
-            0x1 => Some(E),
-            0x9 => Some(F),
-            0x5 => Some(G),
-            0xD => Some(H),
-            0x7 => Some(I),
-            0xF => Some(J),
-            0x3 => Some(K),
-            0xB => Some(L),
-            0x0 => Some(M),
-            0x8 => Some(N),
-            0x4 => Some(O),
-            0xC => Some(P),
-            _ => None,
-        }
-    }
-}
-
-impl Into<char> for HouseCode {
-    fn into(self) -> char {
+        <Self as TryFrom<u8>>::try_from(byte.into()).ok()
+    }
+    /// The nibble that encodes the house code on the wire (the inverse of `try_from`).
+    pub fn to_nibble(self) -> u8 {
         use self::HouseCode::*;
         match self {
+            A => 0x6,
+            B => 0xE,
+            C => 0x2,
+            D => 0xA,
+            E => 0x1,
+            F => 0x9,
+            G => 0x5,
+            H => 0xD,
+            I => 0x7,
+            J => 0xF,
+            K => 0x3,
+            L => 0xB,
+            M => 0x0,
+            N => 0x8,
+            O => 0x4,
+            P => 0xC,
+        }
+    }
+}
+
+impl TryFrom<u8> for HouseCode {
+    type Error = DecodeError;
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        use self::HouseCode::*;
+        match byte {
+            0x6 => Ok(A),
+            0xE => Ok(B),
+            0x2 => Ok(C),
+            0xA => Ok(D),
+            0x1 => Ok(E),
+            0x9 => Ok(F),
+            0x5 => Ok(G),
+            0xD => Ok(H),
+            0x7 => Ok(I),
+            0xF => Ok(J),
+            0x3 => Ok(K),
+            0xB => Ok(L),
+            0x0 => Ok(M),
+            0x8 => Ok(N),
+            0x4 => Ok(O),
+            0xC => Ok(P),
+            _ => Err(DecodeError::InvalidNibble(byte)),
+        }
+    }
+}
+
+impl TryFrom<char> for HouseCode {
+    type Error = ParseHouseCodeError;
+    fn try_from(letter: char) -> Result<Self, Self::Error> {
+        use self::HouseCode::*;
+        match letter.to_ascii_uppercase() {
+            'A' => Ok(A),
+            'B' => Ok(B),
+            'C' => Ok(C),
+            'D' => Ok(D),
+            'E' => Ok(E),
+            'F' => Ok(F),
+            'G' => Ok(G),
+            'H' => Ok(H),
+            'I' => Ok(I),
+            'J' => Ok(J),
+            'K' => Ok(K),
+            'L' => Ok(L),
+            'M' => Ok(M),
+            'N' => Ok(N),
+            'O' => Ok(O),
+            'P' => Ok(P),
+            _ => Err(ParseHouseCodeError(letter.to_string())),
+        }
+    }
+}
+
+impl FromStr for HouseCode {
+    type Err = ParseHouseCodeError;
+    /// Parses a single letter (A–P, case-insensitive) as a house code.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(letter), None) => <Self as TryFrom<char>>::try_from(letter),
+            _ => Err(ParseHouseCodeError(s.to_string())),
+        }
+    }
+}
+
+impl From<HouseCode> for char {
+    fn from(house: HouseCode) -> Self {
+        use self::HouseCode::*;
+        match house {
             A => 'A',
             B => 'B',
             C => 'C',
@@ -73,38 +176,67 @@ impl Into<char> for HouseCode {
     }
 }
 
-/// The unit code for an X10 message.
+/// The unit code for an X10 message (1–16).
+///
+/// Prefer [`UnitCode::new`] to constructing this directly; the field is public for matching, but
+/// values outside 1–16 aren't valid X10 units.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct UnitCode(pub u8);
 
 impl UnitCode {
+    /// Creates a unit code, returning `None` unless `unit` is between 1 and 16 (inclusive).
+    pub fn new(unit: u8) -> Option<Self> {
+        if (1..=16).contains(&unit) {
+            Some(UnitCode(unit))
+        } else {
+            None
+        }
+    }
     /// Attempts to convert the argument to a unit code.
     pub fn try_from<T: Into<u8>>(byte: T) -> Option<Self> {
-        match byte.into() {
-            0x6 => Some(UnitCode(1)),
-            0xE => Some(UnitCode(2)),
-            0x2 => Some(UnitCode(3)),
-            0xA => Some(UnitCode(4)),
-            0x1 => Some(UnitCode(5)),
-            0x9 => Some(UnitCode(6)),
-            0x5 => Some(UnitCode(7)),
-            0xD => Some(UnitCode(8)),
-            0x7 => Some(UnitCode(9)),
-            0xF => Some(UnitCode(10)),
-            0x3 => Some(UnitCode(11)),
-            0xB => Some(UnitCode(12)),
-            0x0 => Some(UnitCode(13)),
-            0x8 => Some(UnitCode(14)),
-            0x4 => Some(UnitCode(15)),
-            0xC => Some(UnitCode(16)),
-            _ => None,
+        <Self as TryFrom<u8>>::try_from(byte.into()).ok()
+    }
+    /// The nibble that encodes the unit code on the wire (the inverse of `try_from`).
+    ///
+    /// Units are encoded with the same nibbles as house codes (unit 1 like A, unit 2 like B, and
+    /// so on). Returns `None` for invalid units (see `new`).
+    pub fn to_nibble(self) -> Option<u8> {
+        const NIBBLES: [u8; 16] = [
+            0x6, 0xE, 0x2, 0xA, 0x1, 0x9, 0x5, 0xD, 0x7, 0xF, 0x3, 0xB, 0x0, 0x8, 0x4, 0xC,
+        ];
+        NIBBLES.get(usize::from(self.0).checked_sub(1)?).cloned()
+    }
+}
+
+impl TryFrom<u8> for UnitCode {
+    type Error = DecodeError;
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            0x6 => Ok(UnitCode(1)),
+            0xE => Ok(UnitCode(2)),
+            0x2 => Ok(UnitCode(3)),
+            0xA => Ok(UnitCode(4)),
+            0x1 => Ok(UnitCode(5)),
+            0x9 => Ok(UnitCode(6)),
+            0x5 => Ok(UnitCode(7)),
+            0xD => Ok(UnitCode(8)),
+            0x7 => Ok(UnitCode(9)),
+            0xF => Ok(UnitCode(10)),
+            0x3 => Ok(UnitCode(11)),
+            0xB => Ok(UnitCode(12)),
+            0x0 => Ok(UnitCode(13)),
+            0x8 => Ok(UnitCode(14)),
+            0x4 => Ok(UnitCode(15)),
+            0xC => Ok(UnitCode(16)),
+            _ => Err(DecodeError::InvalidNibble(byte)),
         }
     }
 }
 
-impl Into<u8> for UnitCode {
-    fn into(self) -> u8 {
-        self.0
+impl From<UnitCode> for u8 {
+    fn from(unit: UnitCode) -> Self {
+        unit.0
     }
 }
 
@@ -116,11 +248,13 @@ impl fmt::Display for UnitCode {
 
 /// An X10 command.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Command {
     AllLightsOff,
     StatusOff,
     On,
-    PresetDim,
+    /// The first half of a preset dim, for levels with the high bit clear.
+    PresetDim1,
     AllLightsOn,
     HailAcknowledge,
     Bright,
@@ -128,6 +262,8 @@ pub enum Command {
     ExtendedCode,
     StatusRequest,
     Off,
+    /// The second half of a preset dim, for levels with the high bit set.
+    PresetDim2,
     AllUnitsOff,
     HailRequest,
     Dim,
@@ -137,24 +273,52 @@ pub enum Command {
 impl Command {
     /// Attempts to convert the argument to an X10 command.
     pub fn try_from<T: Into<u8>>(byte: T) -> Option<Self> {
-        match byte.into() {
-            0x6 => Some(Command::AllLightsOff),
-            0xE => Some(Command::StatusOff),
-            0x2 => Some(Command::On),
-            0xA => Some(Command::PresetDim),
-            0x1 => Some(Command::AllLightsOn),
-            0x9 => Some(Command::HailAcknowledge),
-            0x5 => Some(Command::Bright),
-            0xD => Some(Command::StatusOn),
-            0x7 => Some(Command::ExtendedCode),
-            0xF => Some(Command::StatusRequest),
-            0x3 => Some(Command::Off),
-            0xB => Some(Command::PresetDim),
-            0x0 => Some(Command::AllUnitsOff),
-            0x8 => Some(Command::HailRequest),
-            0x4 => Some(Command::Dim),
-            0xC => Some(Command::ExtendedAnalogData),
-            _ => None,
+        <Self as TryFrom<u8>>::try_from(byte.into()).ok()
+    }
+    /// The nibble that encodes the command on the wire (the inverse of `try_from`).
+    pub fn to_nibble(self) -> u8 {
+        match self {
+            Command::AllLightsOff => 0x6,
+            Command::StatusOff => 0xE,
+            Command::On => 0x2,
+            Command::PresetDim1 => 0xA,
+            Command::AllLightsOn => 0x1,
+            Command::HailAcknowledge => 0x9,
+            Command::Bright => 0x5,
+            Command::StatusOn => 0xD,
+            Command::ExtendedCode => 0x7,
+            Command::StatusRequest => 0xF,
+            Command::Off => 0x3,
+            Command::PresetDim2 => 0xB,
+            Command::AllUnitsOff => 0x0,
+            Command::HailRequest => 0x8,
+            Command::Dim => 0x4,
+            Command::ExtendedAnalogData => 0xC,
+        }
+    }
+}
+
+impl TryFrom<u8> for Command {
+    type Error = DecodeError;
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            0x6 => Ok(Command::AllLightsOff),
+            0xE => Ok(Command::StatusOff),
+            0x2 => Ok(Command::On),
+            0xA => Ok(Command::PresetDim1),
+            0x1 => Ok(Command::AllLightsOn),
+            0x9 => Ok(Command::HailAcknowledge),
+            0x5 => Ok(Command::Bright),
+            0xD => Ok(Command::StatusOn),
+            0x7 => Ok(Command::ExtendedCode),
+            0xF => Ok(Command::StatusRequest),
+            0x3 => Ok(Command::Off),
+            0xB => Ok(Command::PresetDim2),
+            0x0 => Ok(Command::AllUnitsOff),
+            0x8 => Ok(Command::HailRequest),
+            0x4 => Ok(Command::Dim),
+            0xC => Ok(Command::ExtendedAnalogData),
+            _ => Err(DecodeError::InvalidNibble(byte)),
         }
     }
 }
@@ -168,7 +332,7 @@ impl fmt::Display for Command {
                 Command::AllLightsOff => "All Lights Off",
                 Command::StatusOff => "Status = Off",
                 Command::On => "On",
-                Command::PresetDim => "Preset Dim",
+                Command::PresetDim1 => "Preset Dim 1",
                 Command::AllLightsOn => "All Lights On",
                 Command::HailAcknowledge => "Hail Acknowledge",
                 Command::Bright => "Bright",
@@ -176,6 +340,7 @@ impl fmt::Display for Command {
                 Command::ExtendedCode => "Extended Code",
                 Command::StatusRequest => "Status Request",
                 Command::Off => "Off",
+                Command::PresetDim2 => "Preset Dim 2",
                 Command::AllUnitsOff => "All Units Off",
                 Command::HailRequest => "Hail Request",
                 Command::Dim => "Dim",
@@ -187,43 +352,178 @@ impl fmt::Display for Command {
 
 /// The X10 message payload; either a unit code or a command.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Payload {
     UnitCode(UnitCode),
     Command(Command),
 }
 
+impl Payload {
+    /// The phase of the transmission that carries this payload: unit codes are sent in the
+    /// address phase and commands in the command phase.
+    pub fn flag(self) -> Flag {
+        match self {
+            Payload::UnitCode(_) => Flag::Address,
+            Payload::Command(_) => Flag::Command,
+        }
+    }
+    /// The low nibble and the flag byte (`0x00` or `0x80`) that encode the payload on the wire
+    /// (the inverse of the `TryFrom<(u8, u8)>` conversion).
+    ///
+    /// The house code goes in the high nibble. Returns `None` for invalid units (see
+    /// `UnitCode::new`).
+    pub fn to_nibble_and_flag(self) -> Option<(u8, u8)> {
+        match self {
+            Payload::UnitCode(unit) => Some((unit.to_nibble()?, self.flag().into())),
+            Payload::Command(command) => Some((command.to_nibble(), self.flag().into())),
+        }
+    }
+}
+
+/// Decodes a payload from its low nibble and flag byte (of which only the top bit is read; see
+/// `Message`).
+impl TryFrom<(u8, u8)> for Payload {
+    type Error = DecodeError;
+    fn try_from((nibble, flag): (u8, u8)) -> Result<Self, Self::Error> {
+        Ok(match Flag::try_from(flag & 0x80)? {
+            Flag::Address => Payload::UnitCode(<UnitCode as TryFrom<u8>>::try_from(nibble)?),
+            Flag::Command => Payload::Command(<Command as TryFrom<u8>>::try_from(nibble)?),
+        })
+    }
+}
+
+/// The X10 flag byte, which says which phase of an X10 transmission a frame belongs to.
+///
+/// X10 devices are controlled by first selecting a unit (the address phase) and then sending a
+/// command to it (the command phase).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Flag {
+    /// The frame selects a unit (`0x00`).
+    Address,
+    /// The frame carries a command (`0x80`).
+    Command,
+}
+
+impl TryFrom<u8> for Flag {
+    type Error = DecodeError;
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            0x00 => Ok(Flag::Address),
+            0x80 => Ok(Flag::Command),
+            _ => Err(DecodeError::InvalidFlag(byte)),
+        }
+    }
+}
+
+impl From<Flag> for u8 {
+    fn from(flag: Flag) -> Self {
+        match flag {
+            Flag::Address => 0x00,
+            Flag::Command => 0x80,
+        }
+    }
+}
+
+/// The status byte that ends an X10 message.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Ack {
+    /// The message was acknowledged (`0x06`).
+    Ack,
+    /// The message was refused (`0x15`).
+    Nak,
+    /// The status byte was neither an ACK nor a NAK.
+    Unknown(u8),
+}
+
+impl From<u8> for Ack {
+    fn from(byte: u8) -> Self {
+        match byte {
+            0x06 => Ack::Ack,
+            0x15 => Ack::Nak,
+            byte => Ack::Unknown(byte),
+        }
+    }
+}
+
+impl From<Ack> for u8 {
+    fn from(ack: Ack) -> Self {
+        match ack {
+            Ack::Ack => 0x06,
+            Ack::Nak => 0x15,
+            Ack::Unknown(byte) => byte,
+        }
+    }
+}
+
+impl fmt::Display for Ack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ack::Ack => write!(f, "succeeded"),
+            Ack::Nak => write!(f, "failed (NAK)"),
+            Ack::Unknown(byte) => write!(f, "ended with unknown status {:#04x}", byte),
+        }
+    }
+}
+
 /// An X10 message, as communicated by Insteon's network.
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Message {
     /// The house code from the message.
     pub house: HouseCode,
     /// The message payload (either a unit code or a command).
     pub payload: Payload,
-    /// Whether the command was successful.
-    pub success: bool,
+    /// Whether the message was acknowledged, if it was sent by the modem (received X10 messages
+    /// carry no status).
+    pub status: Option<Ack>,
 }
 
 impl Message {
-    /// Attempts to parse the given byte array as an X10 message.
+    /// Attempts to parse the given byte array as an X10 message sent by the modem (the raw byte,
+    /// the flag byte, and the status byte).
     pub fn try_from<B: Into<[u8; 3]>>(bytes: B) -> Option<Self> {
-        let bytes = bytes.into();
+        <Self as TryFrom<[u8; 3]>>::try_from(bytes.into()).ok()
+    }
+    /// The phase of the transmission this message belongs to (see `Payload::flag`).
+    pub fn flag(&self) -> Flag {
+        self.payload.flag()
+    }
+    /// Whether this message selects a unit (the address phase).
+    pub fn is_address(&self) -> bool {
+        self.flag() == Flag::Address
+    }
+    /// Whether this message carries a command (the command phase).
+    pub fn is_command(&self) -> bool {
+        self.flag() == Flag::Command
+    }
+}
+
+impl TryFrom<[u8; 3]> for Message {
+    type Error = DecodeError;
+    fn try_from(bytes: [u8; 3]) -> Result<Self, Self::Error> {
         let byte = bytes[0];
         let high = byte >> 4;
         let low = byte & 0x0f;
-        let flag = bytes[1];
-        let success = bytes[2] == 0x06;
-        HouseCode::try_from(high).and_then(|house| match flag {
-            0x00 => UnitCode::try_from(low).map(|unit| Message {
-                house,
-                payload: Payload::UnitCode(unit),
-                success,
-            }),
-            0x80 => Command::try_from(low).map(|cmd| Message {
-                house,
-                payload: Payload::Command(cmd),
-                success,
-            }),
-            _ => None,
+        let house = <HouseCode as TryFrom<u8>>::try_from(high)?;
+        let payload = Payload::try_from((low, bytes[1]))?;
+        Ok(Message {
+            house,
+            payload,
+            status: Some(bytes[2].into()),
+        })
+    }
+}
+
+/// Decodes a received X10 message (the raw byte and the flag byte), which has no status.
+impl TryFrom<[u8; 2]> for Message {
+    type Error = DecodeError;
+    fn try_from(bytes: [u8; 2]) -> Result<Self, Self::Error> {
+        let message = <Self as TryFrom<[u8; 3]>>::try_from([bytes[0], bytes[1], 0x00])?;
+        Ok(Message {
+            status: None,
+            ..message
         })
     }
 }
@@ -231,10 +531,82 @@ impl Message {
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let h: char = self.house.into();
-        let status = if self.success { "succeeded" } else { "failed" };
         match &self.payload {
-            Payload::UnitCode(unit) => write!(f, "{}{} {}", h, unit, status),
-            Payload::Command(cmd) => write!(f, "Command {} ({}) {}", cmd, h, status),
+            Payload::UnitCode(unit) => write!(f, "{}{}", h, unit)?,
+            Payload::Command(cmd) => write!(f, "Command {} ({})", cmd, h)?,
+        }
+        match self.status {
+            Some(status) => write!(f, " {}", status),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn house_code_from_str() {
+        for letter in "ABCDEFGHIJKLMNOP".chars() {
+            let house: HouseCode = letter.to_string().parse().unwrap();
+            assert_eq!(char::from(house), letter);
+            let lower: HouseCode = letter.to_ascii_lowercase().to_string().parse().unwrap();
+            assert_eq!(lower, house);
+        }
+        assert!("Q".parse::<HouseCode>().is_err());
+        assert!("".parse::<HouseCode>().is_err());
+        assert!("AB".parse::<HouseCode>().is_err());
+    }
+
+    #[test]
+    fn unit_code_range() {
+        assert_eq!(UnitCode::new(0), None);
+        assert_eq!(UnitCode::new(1), Some(UnitCode(1)));
+        assert_eq!(UnitCode::new(16), Some(UnitCode(16)));
+        assert_eq!(UnitCode::new(17), None);
+    }
+
+    #[test]
+    fn flag_extra_bits() {
+        // House code A (0x6), unit 1 (0x6) and command On (0x2), with stray low bits set.
+        let address = Message::try_from([0x66, 0x01, 0x06]).unwrap();
+        assert!(address.is_address());
+        let command = Message::try_from([0x62, 0x81, 0x06]).unwrap();
+        assert!(command.is_command());
+        assert_eq!(command.payload, Payload::Command(Command::On));
+        assert_eq!(Flag::try_from(0x81), Err(DecodeError::InvalidFlag(0x81)));
+    }
+
+    #[test]
+    fn status_byte() {
+        let nak = Message::try_from([0x62, 0x80, 0x15]).unwrap();
+        assert_eq!(nak.status, Some(Ack::Nak));
+        assert_eq!(nak.to_string(), "Command On (A) failed (NAK)");
+        let unknown = Message::try_from([0x62, 0x80, 0x42]).unwrap();
+        assert_eq!(unknown.status, Some(Ack::Unknown(0x42)));
+        let received = <Message as TryFrom<[u8; 2]>>::try_from([0x62, 0x80]).unwrap();
+        assert_eq!(received.status, None);
+        assert_eq!(received.to_string(), "Command On (A)");
+    }
+
+    #[test]
+    fn command_nibbles() {
+        for nibble in 0..16u8 {
+            let command = Command::try_from(nibble).unwrap();
+            assert_eq!(command.to_nibble(), nibble);
+            assert_eq!(Command::try_from(command.to_nibble()), Some(command));
+        }
+    }
+
+    #[test]
+    fn payload_nibbles() {
+        for &flag in &[0x00, 0x80] {
+            for nibble in 0..16u8 {
+                let payload = Payload::try_from((nibble, flag)).unwrap();
+                assert_eq!(payload.to_nibble_and_flag(), Some((nibble, flag)));
+            }
         }
+        assert_eq!(Payload::UnitCode(UnitCode(17)).to_nibble_and_flag(), None);
     }
 }